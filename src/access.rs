@@ -0,0 +1,97 @@
+use regex::Regex;
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::crm::Crm;
+use crate::medfile::{self, Consultation};
+
+#[derive(Serialize)]
+pub struct Grant {
+    pub cpf: String,
+    pub crm: String,
+    pub granted_at: String,
+    pub expires_at: Option<String>,
+    pub revoked_at: Option<String>,
+    pub source: String,
+}
+
+impl Grant {
+    pub fn is_active(&self) -> bool {
+        let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        self.revoked_at.is_none() && self.expires_at.as_ref().is_none_or(|e| *e >= today)
+    }
+}
+
+/// Grants written by the patient in [CONTRACT_CONDITIONS], e.g.
+/// `- Acesso concedido: CRM 10057-SP até 2026-12-31`.
+pub fn contract_grants(content: &str) -> Vec<(String, Option<String>)> {
+    let re = Regex::new(r"(?i)acesso\s+concedido\s*:?\s*CRM\s*([\w/-]+)(?:\s+at[ée]\s+(\d{4}-\d{2}-\d{2}))?").unwrap();
+    let mut grants = Vec::new();
+    let mut in_conditions = false;
+    for line in content.lines() {
+        if let Some(name) = medfile::section_header(line) {
+            in_conditions = name == "CONTRACT_CONDITIONS";
+        } else if in_conditions && let Some(cap) = re.captures(line) {
+            grants.push((Crm::normalize(&cap[1]), cap.get(2).map(|m| m.as_str().to_string())));
+        }
+    }
+    grants
+}
+
+/// Mirrors the grants found in the medfiles into `access_grants`. A grant the
+/// patient revoked stays revoked even though the file still lists it. Files
+/// uploaded through the web (`web_uploads`) are skipped: their conditions were
+/// written by the uploader, not the patient.
+pub fn sync_contract_grants(conn: &Connection, files: &[(std::path::PathBuf, String)]) {
+    let uploaded: Vec<String> = conn.prepare("SELECT filename FROM web_uploads").unwrap()
+        .query_map([], |row| row.get(0)).unwrap()
+        .filter_map(|f| f.ok())
+        .collect();
+    for (path, content) in files {
+        if path.file_name().and_then(|n| n.to_str()).is_some_and(|n| uploaded.iter().any(|u| u == n)) {
+            continue;
+        }
+        let Some(patient) = medfile::parse_patient_from_med(content) else { continue };
+        for (crm, expires_at) in contract_grants(content) {
+            let known: i64 = conn.query_row(
+                "SELECT COUNT(*) FROM access_grants WHERE cpf = ?1 AND crm = ?2 AND source = 'contract' AND expires_at IS ?3",
+                (&patient.cpf, &crm, &expires_at), |row| row.get(0)).unwrap();
+            if known == 0 {
+                conn.execute("INSERT INTO access_grants (cpf, crm, granted_at, expires_at, source) VALUES (?1, ?2, ?3, ?4, 'contract')",
+                             (&patient.cpf, &crm, chrono::Utc::now().to_rfc3339(), &expires_at)).unwrap();
+            }
+        }
+    }
+}
+
+pub fn grant(conn: &Connection, cpf: &str, crm: &str, expires_at: Option<&str>) {
+    conn.execute("INSERT INTO access_grants (cpf, crm, granted_at, expires_at, source) VALUES (?1, ?2, ?3, ?4, 'cli')",
+                 (cpf, crm, chrono::Utc::now().to_rfc3339(), expires_at)).unwrap();
+}
+
+/// Revokes every active grant of `crm` on the patient; returns how many.
+pub fn revoke(conn: &Connection, cpf: &str, crm: &str) -> usize {
+    conn.execute("UPDATE access_grants SET revoked_at = ?3 WHERE cpf = ?1 AND crm = ?2 AND revoked_at IS NULL",
+                 (cpf, crm, chrono::Utc::now().to_rfc3339())).unwrap()
+}
+
+pub fn grants(conn: &Connection, cpf: &str) -> Vec<Grant> {
+    let mut stmt = conn.prepare("SELECT cpf, crm, granted_at, expires_at, revoked_at, source FROM access_grants WHERE cpf = ?1 ORDER BY granted_at").unwrap();
+    stmt.query_map([cpf], |row| {
+        Ok(Grant {
+            cpf: row.get(0)?,
+            crm: row.get(1)?,
+            granted_at: row.get(2)?,
+            expires_at: row.get(3)?,
+            revoked_at: row.get(4)?,
+            source: row.get(5)?,
+        })
+    }).unwrap().filter_map(|g| g.ok()).collect()
+}
+
+/// A doctor may read a patient's record when they authored one of its
+/// consultations or hold an active grant.
+pub fn can_read(conn: &Connection, cpf: &str, crm: &str, consultations: &[Consultation]) -> bool {
+    consultations.iter().any(|c| c.patient.cpf == cpf && c.doctor.crm == crm)
+        || grants(conn, cpf).iter().any(|g| g.crm == crm && g.is_active())
+}
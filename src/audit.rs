@@ -0,0 +1,72 @@
+use rusqlite::Connection;
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub username: String,
+    pub action: String,
+    pub cpf: Option<String>,
+    pub route: String,
+    pub timestamp: String,
+    pub ip: Option<String>,
+}
+
+/// How changes made from the command line are attributed: `cli:` and the OS user.
+pub fn cli_user() -> String {
+    format!("cli:{}", std::env::var("USER").ok().filter(|u| !u.is_empty()).unwrap_or_else(|| "desconhecido".to_string()))
+}
+
+/// Appends an entry; the table refuses updates and deletes.
+pub fn record(conn: &Connection, username: &str, action: &str, cpf: Option<&str>, route: &str, ip: Option<&str>) {
+    conn.execute("INSERT INTO audit_log (username, action, cpf, route, timestamp, ip) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                 (username, action, cpf, route, chrono::Utc::now().to_rfc3339(), ip)).unwrap();
+}
+
+pub fn query(conn: &Connection, cpf: Option<&str>, username: Option<&str>) -> Vec<AuditEntry> {
+    let mut stmt = conn.prepare("SELECT id, username, action, cpf, route, timestamp, ip FROM audit_log
+                                 WHERE (?1 IS NULL OR cpf = ?1) AND (?2 IS NULL OR username = ?2) ORDER BY id").unwrap();
+    stmt.query_map((cpf, username), |row| {
+        Ok(AuditEntry {
+            id: row.get(0)?,
+            username: row.get(1)?,
+            action: row.get(2)?,
+            cpf: row.get(3)?,
+            route: row.get(4)?,
+            timestamp: row.get(5)?,
+            ip: row.get(6)?,
+        })
+    }).unwrap().filter_map(|e| e.ok()).collect()
+}
+
+#[derive(Serialize)]
+pub struct AccessSummary {
+    pub username: String,
+    pub nome: Option<String>,
+    pub role: Option<String>,
+    pub crm: Option<String>,
+    pub action: String,
+    pub count: i64,
+    pub first_access: String,
+    pub last_access: String,
+}
+
+/// Who accessed the patient's data, one line per user and action, for LGPD
+/// data subject requests.
+pub fn access_report(conn: &Connection, cpf: &str) -> Vec<AccessSummary> {
+    let mut stmt = conn.prepare("SELECT a.username, u.nome, u.role, u.crm, a.action, COUNT(*), MIN(a.timestamp), MAX(a.timestamp)
+                                 FROM audit_log a LEFT JOIN users u ON u.username = a.username
+                                 WHERE a.cpf = ?1 GROUP BY a.username, a.action ORDER BY MAX(a.timestamp) DESC").unwrap();
+    stmt.query_map([cpf], |row| {
+        Ok(AccessSummary {
+            username: row.get(0)?,
+            nome: row.get(1)?,
+            role: row.get(2)?,
+            crm: row.get(3)?,
+            action: row.get(4)?,
+            count: row.get(5)?,
+            first_access: row.get(6)?,
+            last_access: row.get(7)?,
+        })
+    }).unwrap().filter_map(|r| r.ok()).collect()
+}
@@ -0,0 +1,130 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use rand::RngCore;
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+
+pub const SESSION_COOKIE: &str = "mymed_session";
+const SESSION_HOURS: i64 = 12;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Doctor,
+    Receptionist,
+    Admin,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Permission {
+    /// Name, CPF, age and the date and doctor of each consultation.
+    ReadDemographics,
+    /// Registering and correcting patients' identification.
+    WriteDemographics,
+    /// Diagnoses, conduct and medications.
+    ReadClinical,
+    /// Authoring consultations.
+    WriteClinical,
+    ManageUsers,
+}
+
+impl Role {
+    pub fn can(&self, permission: Permission) -> bool {
+        match self {
+            Role::Doctor => matches!(permission, Permission::ReadDemographics | Permission::WriteDemographics
+                                                 | Permission::ReadClinical | Permission::WriteClinical),
+            Role::Receptionist => matches!(permission, Permission::ReadDemographics | Permission::WriteDemographics),
+            Role::Admin => matches!(permission, Permission::ReadDemographics | Permission::ManageUsers),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Doctor => "doctor",
+            Role::Receptionist => "receptionist",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Role> {
+        match s {
+            "doctor" | "medico" => Some(Role::Doctor),
+            "receptionist" | "recepcao" => Some(Role::Receptionist),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    pub nome: String,
+    pub role: Role,
+    pub crm: Option<String>,
+}
+
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default().hash_password(password.as_bytes(), &salt).unwrap().to_string()
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+}
+
+pub fn create_user(conn: &Connection, username: &str, nome: &str, password: &str, role: Role, crm: Option<&str>) -> Result<(), String> {
+    if role == Role::Doctor && crm.is_none() {
+        return Err("Médicos precisam de CRM.".to_string());
+    }
+    conn.execute("INSERT INTO users (username, nome, password_hash, role, crm, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                 (username, nome, hash_password(password), role.as_str(), crm, chrono::Utc::now().to_rfc3339()))
+        .map_err(|_| format!("Usuário {} já existe.", username))?;
+    Ok(())
+}
+
+fn user_from_row(row: &rusqlite::Row) -> rusqlite::Result<User> {
+    let role: String = row.get(3)?;
+    Ok(User {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        nome: row.get(2)?,
+        role: Role::parse(&role).unwrap_or(Role::Receptionist),
+        crm: row.get(4)?,
+    })
+}
+
+pub fn users(conn: &Connection) -> Vec<User> {
+    let mut stmt = conn.prepare("SELECT id, username, nome, role, crm FROM users ORDER BY username").unwrap();
+    stmt.query_map([], user_from_row).unwrap().filter_map(|u| u.ok()).collect()
+}
+
+/// Checks the credentials and opens a session, returning its token.
+pub fn login(conn: &Connection, username: &str, password: &str) -> Option<(String, User)> {
+    let (user, hash) = conn.query_row("SELECT id, username, nome, role, crm, password_hash FROM users WHERE username = ?1", [username],
+                                      |row| Ok((user_from_row(row)?, row.get::<_, String>(5)?)))
+        .optional().unwrap()?;
+    if !verify_password(password, &hash) {
+        return None;
+    }
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+    let now = chrono::Utc::now();
+    conn.execute("INSERT INTO sessions (token, user_id, created_at, expires_at) VALUES (?1, ?2, ?3, ?4)",
+                 (&token, user.id, now.to_rfc3339(), (now + chrono::Duration::hours(SESSION_HOURS)).to_rfc3339())).unwrap();
+    Some((token, user))
+}
+
+pub fn session_user(conn: &Connection, token: &str) -> Option<User> {
+    conn.query_row("SELECT u.id, u.username, u.nome, u.role, u.crm FROM sessions s JOIN users u ON s.user_id = u.id
+                    WHERE s.token = ?1 AND s.expires_at > ?2",
+                   [token, &chrono::Utc::now().to_rfc3339()], user_from_row)
+        .optional().ok()?
+}
+
+pub fn logout(conn: &Connection, token: &str) {
+    conn.execute("DELETE FROM sessions WHERE token = ?1", [token]).unwrap();
+}
@@ -0,0 +1,82 @@
+/// Common CID-10 codes offered when completing [HIPOTESE DIAGNOSTICA].
+pub const ENTRIES: &[(&str, &str)] = &[
+    ("A09", "Diarreia e gastroenterite de origem infecciosa presumível"),
+    ("B34.9", "Infecção viral não especificada"),
+    ("C50.9", "Neoplasia maligna da mama, não especificada"),
+    ("C61", "Neoplasia maligna da próstata"),
+    ("D50.9", "Anemia por deficiência de ferro não especificada"),
+    ("E03.9", "Hipotireoidismo não especificado"),
+    ("E05.9", "Tireotoxicose não especificada"),
+    ("E11.9", "Diabetes mellitus tipo 2 sem complicações"),
+    ("E10.9", "Diabetes mellitus tipo 1 sem complicações"),
+    ("E66.9", "Obesidade não especificada"),
+    ("E78.0", "Hipercolesterolemia pura"),
+    ("E78.5", "Hiperlipidemia não especificada"),
+    ("E55.9", "Deficiência de vitamina D não especificada"),
+    ("F00.9", "Demência na doença de Alzheimer, não especificada"),
+    ("F01.9", "Demência vascular não especificada"),
+    ("F03", "Demência não especificada"),
+    ("F10.2", "Transtornos mentais devidos ao uso de álcool - síndrome de dependência"),
+    ("F17.2", "Transtornos devidos ao uso de fumo - síndrome de dependência"),
+    ("F20.9", "Esquizofrenia não especificada"),
+    ("F31.9", "Transtorno afetivo bipolar não especificado"),
+    ("F32.9", "Episódio depressivo não especificado"),
+    ("F33.9", "Transtorno depressivo recorrente sem especificação"),
+    ("F41.0", "Transtorno de pânico"),
+    ("F41.1", "Ansiedade generalizada"),
+    ("F41.2", "Transtorno misto ansioso e depressivo"),
+    ("F42.9", "Transtorno obsessivo-compulsivo não especificado"),
+    ("F43.1", "Estado de stress pós-traumático"),
+    ("F51.0", "Insônia não-orgânica"),
+    ("F84.0", "Autismo infantil"),
+    ("F90.0", "Distúrbios da atividade e da atenção"),
+    ("G20", "Doença de Parkinson"),
+    ("G25.0", "Tremor essencial"),
+    ("G30.9", "Doença de Alzheimer não especificada"),
+    ("G35", "Esclerose múltipla"),
+    ("G40.9", "Epilepsia não especificada"),
+    ("G43.9", "Enxaqueca sem especificação"),
+    ("G44.2", "Cefaleia tensional"),
+    ("G45.9", "Isquemia cerebral transitória não especificada"),
+    ("G47.0", "Distúrbios do início e da manutenção do sono (insônias)"),
+    ("G47.3", "Apneia de sono"),
+    ("G62.9", "Polineuropatia não especificada"),
+    ("G81.9", "Hemiplegia não especificada"),
+    ("G91.9", "Hidrocefalia não especificada"),
+    ("I10", "Hipertensão essencial (primária)"),
+    ("I20.9", "Angina pectoris não especificada"),
+    ("I21.9", "Infarto agudo do miocárdio não especificado"),
+    ("I25.1", "Doença aterosclerótica do coração"),
+    ("I48", "Flutter e fibrilação atrial"),
+    ("I50.9", "Insuficiência cardíaca não especificada"),
+    ("I60.9", "Hemorragia subaracnóide não especificada"),
+    ("I63.9", "Infarto cerebral não especificado"),
+    ("I64", "Acidente vascular cerebral, não especificado como hemorrágico ou isquêmico"),
+    ("I69.4", "Seqüelas de acidente vascular cerebral"),
+    ("I83.9", "Varizes dos membros inferiores sem úlcera ou inflamação"),
+    ("J06.9", "Infecção aguda das vias aéreas superiores não especificada"),
+    ("J18.9", "Pneumonia não especificada"),
+    ("J30.4", "Rinite alérgica não especificada"),
+    ("J44.9", "Doença pulmonar obstrutiva crônica não especificada"),
+    ("J45.9", "Asma não especificada"),
+    ("K21.9", "Doença de refluxo gastroesofágico sem esofagite"),
+    ("K29.7", "Gastrite não especificada"),
+    ("K58.9", "Síndrome do cólon irritável sem diarreia"),
+    ("K59.0", "Constipação"),
+    ("K76.0", "Degeneração gordurosa do fígado"),
+    ("L20.9", "Dermatite atópica não especificada"),
+    ("M10.9", "Gota não especificada"),
+    ("M17.9", "Gonartrose não especificada"),
+    ("M54.2", "Cervicalgia"),
+    ("M54.5", "Dor lombar baixa"),
+    ("M79.7", "Fibromialgia"),
+    ("M81.9", "Osteoporose não especificada"),
+    ("N18.9", "Doença renal crônica não especificada"),
+    ("N39.0", "Infecção do trato urinário de localização não especificada"),
+    ("N40", "Hiperplasia da próstata"),
+    ("R51", "Cefaleia"),
+    ("R42", "Tontura e instabilidade"),
+    ("R56.8", "Outras convulsões e as não especificadas"),
+    ("Z00.0", "Exame médico geral"),
+    ("Z76.0", "Emissão de prescrição de repetição"),
+];
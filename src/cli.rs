@@ -0,0 +1,103 @@
+use crossterm::event::{read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+
+use crate::{audit, db};
+
+/// Prints the result of a command: `message` for people, `value` with `--json`.
+pub fn report(json: bool, value: serde_json::Value, message: impl std::fmt::Display) {
+    if json {
+        println!("{}", serde_json::to_string_pretty(&value).unwrap());
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// Prints `message` on stderr, or `{"error": ...}` on stdout with `--json`,
+/// and exits with status 1.
+pub fn fail(json: bool, message: impl std::fmt::Display) -> ! {
+    if json {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "error": message.to_string() })).unwrap());
+    } else {
+        eprintln!("{}", message);
+    }
+    std::process::exit(1);
+}
+
+/// Asks on the terminal and returns the trimmed answer. Questions go to
+/// stderr so that `--json` output stays parseable.
+pub fn prompt(question: &str) -> String {
+    ask(question).unwrap_or_default()
+}
+
+/// Like `prompt`, but `None` at the end of input (Ctrl-D), for questions
+/// that would otherwise be repeated forever.
+pub fn ask(question: &str) -> Option<String> {
+    eprintln!("{}", question);
+    let mut answer = String::new();
+    match std::io::stdin().read_line(&mut answer).unwrap() {
+        0 => None,
+        _ => Some(answer.trim().to_string()),
+    }
+}
+
+/// Reads a password from the terminal without echoing it. `None` if the
+/// user gives up with Esc, Ctrl-C or Ctrl-D.
+pub fn password(question: &str) -> Option<String> {
+    eprintln!("{}", question);
+    enable_raw_mode().unwrap();
+    let mut password = String::new();
+    let typed = loop {
+        let Event::Key(KeyEvent { code, modifiers, kind: KeyEventKind::Press, .. }) = read().unwrap() else { continue };
+        match code {
+            KeyCode::Enter => break Some(password),
+            KeyCode::Esc => break None,
+            KeyCode::Char('c' | 'd') if modifiers.contains(KeyModifiers::CONTROL) => break None,
+            KeyCode::Backspace => { password.pop(); }
+            KeyCode::Char(c) => password.push(c),
+            _ => {}
+        }
+    };
+    disable_raw_mode().unwrap();
+    typed
+}
+
+pub fn interactive() -> bool {
+    atty::is(atty::Stream::Stdin)
+}
+
+/// `--yes`, or an s/n question when a terminal is attached.
+pub fn confirm(json: bool, yes: bool, question: &str) -> bool {
+    if yes {
+        return true;
+    }
+    if !interactive() {
+        fail(json, "Use --yes para confirmar sem terminal.");
+    }
+    prompt(&format!("{} [s/N]", question)).eq_ignore_ascii_case("s")
+}
+
+/// Records a change made from the command line, attributed to the OS user.
+pub fn audit(action: &str, cpf: Option<&str>, command: &str) {
+    audit::record(&db::open(), &audit::cli_user(), action, cpf, command, None);
+}
+
+/// Word-wraps each line of `text` to `width` characters, for the terminal
+/// panes of the editor and the patient list. A word longer than the width
+/// stays whole on its own line.
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut out = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+                out.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        out.push(line);
+    }
+    out
+}
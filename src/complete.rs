@@ -0,0 +1,154 @@
+use regex::Regex;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::medfile::{self, fold};
+use crate::{cid10, diagnostics};
+
+/// Most suggestions returned for one request.
+pub const LIMIT: usize = 20;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Context {
+    /// `!NOME` directives.
+    Directive,
+    /// Drug names after !PRESCREVO/!AJUSTO/!SUSPENDO/!MANTENHO.
+    Drug,
+    /// Exam codes written as `!EX @CODIGO`.
+    Exam,
+    /// CID-10 codes for [HIPOTESE DIAGNOSTICA].
+    Cid,
+}
+
+impl Context {
+    pub fn parse(s: &str) -> Option<Context> {
+        match s {
+            "directive" => Some(Context::Directive),
+            "drug" => Some(Context::Drug),
+            "exam" => Some(Context::Exam),
+            "cid" => Some(Context::Cid),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Completion {
+    pub label: String,
+    /// Description of a CID-10 code, or the usual doses of a drug.
+    pub detail: Option<String>,
+    /// Times the requesting doctor used it; ranks first.
+    pub doctor_uses: usize,
+    pub uses: usize,
+}
+
+#[derive(Default)]
+struct Usage {
+    all: HashMap<String, usize>,
+    doctor: HashMap<String, usize>,
+}
+
+impl Usage {
+    fn add(&mut self, term: &str, mine: bool) {
+        *self.all.entry(term.to_string()).or_default() += 1;
+        if mine {
+            *self.doctor.entry(term.to_string()).or_default() += 1;
+        }
+    }
+
+    fn completion(&self, label: &str, detail: Option<String>) -> Completion {
+        Completion {
+            label: label.to_string(),
+            detail,
+            doctor_uses: self.doctor.get(label).copied().unwrap_or(0),
+            uses: self.all.get(label).copied().unwrap_or(0),
+        }
+    }
+}
+
+/// Suggestions for `prefix` in the given context, ranked by how often the
+/// doctor `crm` used each one, then by overall use.
+pub fn complete(conn: &Connection, files: &[(PathBuf, String)], crm: Option<&str>, ctx: Context, prefix: &str) -> Vec<Completion> {
+    let directive_re = Regex::new(r"!([A-Za-z]\w*)").unwrap();
+    let prefix = fold(prefix.trim().trim_start_matches(['!', '@']));
+    let mut usage = Usage::default();
+    let mut doses: HashMap<String, Usage> = HashMap::new();
+
+    for (_, content) in files {
+        let Some(cons) = medfile::parse_med_file(content) else { continue };
+        let mine = crm == Some(cons.doctor.crm.as_str());
+        match ctx {
+            Context::Directive => {
+                for cap in directive_re.captures_iter(content) {
+                    usage.add(&cap[1].to_uppercase(), mine);
+                }
+            }
+            Context::Drug => {
+                for (action, med, dosage) in medfile::parse_conduta_commands(&cons.conduta) {
+                    if diagnostics::MEDICATION_DIRECTIVES.contains(&action.as_str()) {
+                        let med = med.trim_matches(['\'', '"', '[', ']']).to_uppercase();
+                        if med.is_empty() {
+                            continue;
+                        }
+                        usage.add(&med, mine);
+                        let dosage = dosage.trim().trim_matches(['\'', '"']).trim();
+                        if !dosage.is_empty() {
+                            doses.entry(med).or_default().add(dosage, mine);
+                        }
+                    }
+                }
+            }
+            Context::Exam => {
+                for exam in medfile::parse_exams(content) {
+                    usage.add(&exam.code.to_uppercase(), mine);
+                }
+            }
+            Context::Cid => {
+                for (code, _) in cid10::ENTRIES {
+                    if cons.hipotese_diagnostica.to_uppercase().contains(code) {
+                        usage.add(code, mine);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut out: Vec<Completion> = match ctx {
+        Context::Directive => diagnostics::DIRECTIVES.iter()
+            .filter(|d| fold(d).starts_with(&prefix))
+            .map(|d| usage.completion(d, None))
+            .collect(),
+        Context::Drug => {
+            let mut names: Vec<String> = conn.prepare("SELECT name FROM medications").unwrap()
+                .query_map([], |row| row.get::<_, String>(0)).unwrap()
+                .filter_map(|n| n.ok())
+                .map(|n| n.to_uppercase())
+                .chain(usage.all.keys().cloned())
+                .collect();
+            names.sort();
+            names.dedup();
+            names.iter().filter(|n| fold(n).starts_with(&prefix)).map(|name| {
+                let usual = doses.get(name).map(|d| {
+                    let mut ranked: Vec<_> = d.all.iter().collect();
+                    ranked.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+                    ranked.into_iter().take(3).map(|(dose, _)| dose.as_str()).collect::<Vec<_>>().join(" | ")
+                });
+                usage.completion(name, usual.filter(|u| !u.is_empty()))
+            }).collect()
+        }
+        Context::Exam => usage.all.keys()
+            .filter(|code| fold(code).starts_with(&prefix))
+            .map(|code| usage.completion(code, None))
+            .collect(),
+        Context::Cid => cid10::ENTRIES.iter()
+            .filter(|(code, description)| fold(code).starts_with(&prefix)
+                || fold(description).split_whitespace().any(|w| w.starts_with(&prefix)))
+            .map(|(code, description)| usage.completion(code, Some(description.to_string())))
+            .collect(),
+    };
+    out.sort_by(|a, b| b.doctor_uses.cmp(&a.doctor_uses).then(b.uses.cmp(&a.uses)).then(a.label.cmp(&b.label)));
+    out.truncate(LIMIT);
+    out
+}
@@ -0,0 +1,137 @@
+use regex::Regex;
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::medfile::{self, fold, Consultation, Doctor, Patient};
+use crate::medications::{self, MedicationList};
+use crate::{audit, doctors, ledger, patients, signature, storage};
+
+/// A patient with the history shown next to a new consultation.
+pub struct Chart {
+    pub patient: Patient,
+    /// Consultations in chronological order.
+    pub history: Vec<Consultation>,
+    pub medications: MedicationList,
+}
+
+impl Chart {
+    pub fn last(&self) -> Option<&Consultation> {
+        self.history.last()
+    }
+}
+
+/// Loads patient `cpf` from the registry, or from their latest consultation
+/// when they were never registered.
+pub fn chart(conn: &Connection, cpf: &str) -> Option<Chart> {
+    let mut history: Vec<Consultation> = medfile::load_consultations().into_iter().filter(|c| c.patient.cpf == cpf).collect();
+    medfile::sort_chronologically(&mut history);
+    let patient = patients::get(conn, cpf).or_else(|| history.last().map(|c| c.patient.clone()))?;
+    let medications = medications::current(&history);
+    Some(Chart { patient, history, medications })
+}
+
+/// Exams and referrals ordered with `!SOLICITO`, `!ENCAMINHO` or `!AGUARDO`
+/// that are still open, as `(date, order)`: everything ordered at the last
+/// visit, and earlier orders naming an `@EXAME` with no result reported since.
+pub fn pending_orders(chart: &Chart) -> Vec<(String, String)> {
+    let order_re = Regex::new(r"(?i)!(SOLICITO|ENCAMINHO|AGUARDO)\s*([^;]+)").unwrap();
+    let code_re = Regex::new(r"@(\w+)").unwrap();
+    let reported: Vec<Vec<String>> = chart.history.iter().map(|c| {
+        medfile::find_med_file(&c.filename).and_then(|path| storage::read_med(&path).ok())
+            .map(|content| medfile::parse_exams(&content).into_iter().map(|e| fold(&e.code)).collect())
+            .unwrap_or_default()
+    }).collect();
+    let mut out = Vec::new();
+    for (i, cons) in chart.history.iter().enumerate() {
+        let last = i + 1 == chart.history.len();
+        for cap in order_re.captures_iter(&cons.conduta) {
+            let order = cap[2].trim().trim_matches(['[', ']']).trim();
+            let open = code_re.captures_iter(order)
+                .any(|code| !reported[i + 1..].iter().flatten().any(|r| *r == fold(&code[1])));
+            if last || open {
+                let date = cons.timestamp.get(..10).unwrap_or(&cons.timestamp).to_string();
+                out.push((date, format!("{} {}", cap[1].to_uppercase(), order)));
+            }
+        }
+    }
+    out
+}
+
+/// The doctor with `crm`, named after their user account or their latest
+/// consultation. The specialty comes from that consultation and is empty for
+/// a doctor who never wrote one.
+pub fn doctor(conn: &Connection, crm: &str) -> Option<Doctor> {
+    let latest = medfile::load_consultations().into_iter()
+        .filter(|c| c.doctor.crm == crm)
+        .max_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    let account: Option<String> = conn.query_row("SELECT nome FROM users WHERE crm = ?1", [crm], |row| row.get(0)).optional().unwrap();
+    let nome = account.or_else(|| latest.as_ref().map(|c| c.doctor.nome.clone()))?;
+    Some(Doctor {
+        crm: crm.to_string(),
+        nome,
+        especialidade: latest.map(|c| c.doctor.especialidade).unwrap_or_default(),
+    })
+}
+
+/// Built-in starting texts for the clinical sections, as `(name, sections)`.
+pub fn templates(chart: &Chart) -> Vec<(&'static str, Vec<(&'static str, String)>)> {
+    let meds: Vec<String> = chart.medications.iter().map(|(name, dosage)| format!("{} {}", name, dosage).trim().to_string()).collect();
+    let kept: String = chart.medications.iter()
+        .map(|(name, dosage)| if dosage.is_empty() { format!("!MANTENHO [{}];\n", name) } else { format!("!MANTENHO [{}] [{}];\n", name, dosage) })
+        .collect();
+    let hipotese = chart.last().map(|c| c.hipotese_diagnostica.clone()).unwrap_or_default();
+    vec![
+        ("Em branco", vec![]),
+        ("Primeira consulta", vec![
+            ("ANAMNESE", "QUEIXA PRINCIPAL: \nHISTÓRIA DA DOENÇA ATUAL: \n!HPP \n!HF \n!MED ".to_string()),
+            ("EXAME FISICO", "PA: ; FC: ; PESO: ;".to_string()),
+            ("CONDUTA", "!ORIENTO ;".to_string()),
+        ]),
+        ("Retorno", vec![
+            ("ANAMNESE", format!("RETORNO. \n!MED {}", meds.join("; "))),
+            ("EXAME FISICO", "PA: ; FC: ;".to_string()),
+            ("HIPOTESE DIAGNOSTICA", hipotese.clone()),
+            ("CONDUTA", kept.clone()),
+        ]),
+        ("Renovação de receita", vec![
+            ("ANAMNESE", "RENOVAÇÃO DE RECEITA.".to_string()),
+            ("HIPOTESE DIAGNOSTICA", hipotese),
+            ("CONDUTA", kept),
+        ]),
+    ]
+}
+
+/// Checks a new consultation beyond the parser diagnostics: it must belong
+/// to patient `cpf` and name the doctor's specialty.
+pub fn check_new(content: &str, cpf: &str) -> Result<Consultation, String> {
+    let cons = medfile::parse_med_file(content).ok_or("Arquivo não pôde ser interpretado.")?;
+    if cons.patient.cpf != cpf {
+        return Err(format!("O CPF do cabeçalho difere de {}.", cpf));
+    }
+    if cons.doctor.especialidade.trim().is_empty() {
+        return Err("Informe a especialidade em [DOCTOR].".to_string());
+    }
+    Ok(cons)
+}
+
+/// Stores a new consultation in the medfiles tree as `<paciente>.med`,
+/// sealing it in the ledger when the doctor has a signing key, and refreshes
+/// the patient's medications and the doctor's entry in `doctors`. The
+/// creation is recorded in the audit log under `username` and `route`.
+/// Returns the file name and whether it was signed.
+pub fn save(conn: &Connection, content: &str, username: &str, route: &str, ip: Option<&str>) -> Result<(String, bool), String> {
+    let cons = medfile::parse_med_file(content).ok_or("Arquivo não pôde ser interpretado")?;
+    std::fs::create_dir_all(medfile::medfiles_dir()).map_err(|e| e.to_string())?;
+    let target = medfile::available_path(&format!("{}.med", medfile::file_stem(&cons.patient.nome)));
+    let filename = target.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_string();
+    let signed = signature::has_signing_key(&cons.doctor.crm);
+    if signed {
+        ledger::seal_to_file(conn, content, &target)?;
+    } else {
+        storage::write_med(&target, content).map_err(|e| e.to_string())?;
+    }
+    let history: Vec<Consultation> = medfile::load_consultations().into_iter().filter(|c| c.patient.cpf == cons.patient.cpf).collect();
+    medications::sync(conn, &history)?;
+    doctors::sync(conn, std::slice::from_ref(&cons));
+    audit::record(conn, username, "create_consultation", Some(&cons.patient.cpf), route, ip);
+    Ok((filename, signed))
+}
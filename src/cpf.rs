@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+/// A CPF in the `000.000.000-00` form, the key of a patient in the medfiles,
+/// the database and the routes. Parsing only normalizes the formatting:
+/// existing records may carry wrong check digits, so `is_valid` is checked
+/// where new patients are registered and reported by `mymed check`.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cpf(String);
+
+impl Cpf {
+    /// Accepts the eleven digits with or without `.`, `-` and spaces.
+    pub fn parse(input: &str) -> Result<Cpf, String> {
+        let digits: String = input.chars().filter(|c| c.is_ascii_digit()).collect();
+        if input.chars().any(|c| !c.is_ascii_digit() && !matches!(c, '.' | '-' | ' ')) {
+            return Err(format!("CPF inválido: {}", input.trim()));
+        }
+        if digits.len() != 11 {
+            return Err("CPF deve ter 11 dígitos.".to_string());
+        }
+        Ok(Cpf(format!("{}.{}.{}-{}", &digits[..3], &digits[3..6], &digits[6..9], &digits[9..])))
+    }
+
+    /// The normalized form of `input`, or `input` trimmed when it is not a
+    /// CPF at all, for keys read from files and tables that may hold anything.
+    pub fn normalize(input: &str) -> String {
+        Cpf::parse(input).map(String::from).unwrap_or_else(|_| input.trim().to_string())
+    }
+
+    /// Checks the two verification digits.
+    pub fn is_valid(&self) -> bool {
+        let d: Vec<u32> = self.0.chars().filter_map(|c| c.to_digit(10)).collect();
+        if d.iter().all(|&x| x == d[0]) {
+            return false;
+        }
+        let check = |n: usize| {
+            let sum: u32 = (0..n).map(|i| d[i] * (n as u32 + 1 - i as u32)).sum();
+            (sum * 10 % 11) % 10
+        };
+        check(9) == d[9] && check(10) == d[10]
+    }
+}
+
+impl FromStr for Cpf {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Cpf, String> {
+        Cpf::parse(s)
+    }
+}
+
+impl TryFrom<String> for Cpf {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Cpf, String> {
+        Cpf::parse(&s)
+    }
+}
+
+impl From<Cpf> for String {
+    fn from(cpf: Cpf) -> String {
+        cpf.0
+    }
+}
+
+/// Lets a `Cpf` go wherever the `&str` keys of the lower-level modules do.
+impl Deref for Cpf {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Cpf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_normalizes_the_formatting() {
+        for input in ["12345605700", "123 456 057 00", "123.456.057-00", " 123.456.05700 "] {
+            assert_eq!(Cpf::parse(input).unwrap().to_string(), "123.456.057-00", "{}", input);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_other_characters_and_lengths() {
+        for input in ["", "1234560570", "123456057000", "123.456.057/00", "123.456.O57-00", "CPF 12345605700"] {
+            assert!(Cpf::parse(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn normalize_keeps_what_is_not_a_cpf() {
+        assert_eq!(Cpf::normalize("12345605700"), "123.456.057-00");
+        assert_eq!(Cpf::normalize(" desconhecido "), "desconhecido");
+    }
+
+    #[test]
+    fn known_valid_cpfs() {
+        // 100.000.001-08: the first check digit comes from a remainder of 10.
+        for input in ["529.982.247-25", "111.444.777-35", "123.456.789-09", "100.000.001-08"] {
+            assert!(Cpf::parse(input).unwrap().is_valid(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn known_invalid_cpfs() {
+        // The sample medfiles use 123.456.0NN-00, which fail the check digits.
+        for input in ["529.982.247-26", "529.982.247-15", "123.456.057-00", "123.456.001-00", "111.111.111-11", "000.000.000-00"] {
+            assert!(!Cpf::parse(input).unwrap().is_valid(), "{}", input);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+/// The 27 councils (CRM-UF) that register doctors.
+pub const UFS: &[&str] = &["AC", "AL", "AP", "AM", "BA", "CE", "DF", "ES", "GO", "MA", "MT", "MS", "MG", "PA", "PB", "PR",
+                           "PE", "PI", "RJ", "RN", "RS", "RO", "RR", "SC", "SP", "SE", "TO"];
+
+/// A doctor's registration, number and state council, in the `10057-SP` form
+/// used in the medfiles. The same number in two states is two registrations.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Crm(String);
+
+/// Number and, when written, UF of a CRM as found in free text.
+fn split(input: &str) -> Result<(String, Option<String>), String> {
+    let re = Regex::new(r"^(?:CRM)?[\s:/-]*(?:([A-Z]{2})[\s:/-]*(\d{1,7})|(\d{1,7})(?:[\s:/-]*([A-Z]{2}))?)$").unwrap();
+    let upper = input.trim().to_uppercase();
+    let cap = re.captures(&upper).ok_or_else(|| format!("CRM inválido: {}", input.trim()))?;
+    let number = cap.get(2).or(cap.get(3)).unwrap().as_str().trim_start_matches('0');
+    let uf = cap.get(1).or(cap.get(4)).map(|m| m.as_str().to_string());
+    if let Some(uf) = &uf
+        && !UFS.contains(&uf.as_str()) {
+        return Err(format!("UF do CRM inválida: {}", uf));
+    }
+    if number.is_empty() {
+        return Err(format!("CRM inválido: {}", input.trim()));
+    }
+    Ok((number.to_string(), uf))
+}
+
+impl Crm {
+    /// Accepts `10057-SP`, `10057/SP`, `CRM-SP 10057`, `SP 10057` and the
+    /// like; the UF is required.
+    pub fn parse(input: &str) -> Result<Crm, String> {
+        match split(input)? {
+            (number, Some(uf)) => Ok(Crm(format!("{}-{}", number, uf))),
+            (_, None) => Err(format!("Informe a UF do CRM, ex.: {}-SP.", input.trim())),
+        }
+    }
+
+    /// The normalized form of `input`, or `input` trimmed when it is not a
+    /// CRM, for keys read from files and tables that may hold anything.
+    pub fn normalize(input: &str) -> String {
+        Crm::parse(input).map(String::from).unwrap_or_else(|_| input.trim().to_string())
+    }
+
+    pub fn number(&self) -> &str {
+        self.0.split_once('-').map_or(&self.0, |(number, _)| number)
+    }
+
+    pub fn uf(&self) -> &str {
+        self.0.split_once('-').map_or("", |(_, uf)| uf)
+    }
+
+    /// Whether a CRM written elsewhere, possibly without the UF as in many
+    /// [ASSINATURA] blocks, names this registration.
+    pub fn refers_to(&self, text: &str) -> bool {
+        match split(text) {
+            Ok((number, uf)) => number == self.number() && uf.is_none_or(|uf| uf == self.uf()),
+            Err(_) => false,
+        }
+    }
+}
+
+impl FromStr for Crm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Crm, String> {
+        Crm::parse(s)
+    }
+}
+
+impl TryFrom<String> for Crm {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Crm, String> {
+        Crm::parse(&s)
+    }
+}
+
+impl From<Crm> for String {
+    fn from(crm: Crm) -> String {
+        crm.0
+    }
+}
+
+impl Deref for Crm {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Crm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every form of the same registration that the README lists.
+    const FORMS: &[&str] = &["10057-SP", "10057/SP", "CRM-SP 10057", "SP 10057", "crm 010057 sp"];
+
+    #[test]
+    fn split_reads_number_and_uf() {
+        for input in FORMS {
+            assert_eq!(split(input).unwrap(), ("10057".to_string(), Some("SP".to_string())), "{}", input);
+        }
+        assert_eq!(split("10057").unwrap(), ("10057".to_string(), None));
+        assert_eq!(split("CRM 10057").unwrap(), ("10057".to_string(), None));
+    }
+
+    #[test]
+    fn split_rejects_other_text() {
+        for input in ["", "CRM", "0000-SP", "10057-XX", "12345678-SP", "10057 SP SP", "Dr. Fulano"] {
+            assert!(split(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn parse_normalizes_and_requires_the_uf() {
+        for input in FORMS {
+            let crm = Crm::parse(input).unwrap();
+            assert_eq!(crm.to_string(), "10057-SP", "{}", input);
+            assert_eq!((crm.number(), crm.uf()), ("10057", "SP"));
+        }
+        assert!(Crm::parse("10057").is_err());
+        assert_eq!(Crm::normalize("10057"), "10057");
+    }
+
+    #[test]
+    fn refers_to_matches_the_same_registration() {
+        let crm = Crm::parse("10057-SP").unwrap();
+        for text in FORMS.iter().chain(&["10057", "CRM 10057", "010057"]) {
+            assert!(crm.refers_to(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn refers_to_tells_apart_other_states_and_numbers() {
+        let crm = Crm::parse("10057-SP").unwrap();
+        for text in ["10057-RJ", "RJ 10057", "10058-SP", "1005", "100570", "Dr. Fulano"] {
+            assert!(!crm.refers_to(text), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use rusqlite::Connection;
+
+use crate::cpf::Cpf;
+use crate::crm::Crm;
+
+pub const DB_PATH: &str = "medications.db";
+
+pub fn open() -> Connection {
+    Connection::open(DB_PATH).unwrap()
+}
+
+pub fn setup_db() {
+    let conn = open();
+    create_tables(&conn);
+    normalize_cpfs(&conn);
+    normalize_crms(&conn);
+    // Every binary starts here, so a missing or wrong passphrase stops it with
+    // a message instead of failing on the first encrypted value it reads.
+    if let Err(e) = crate::storage::check_key(&conn) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Creates the tables and triggers that are missing and adds new columns to
+/// old tables.
+pub fn create_tables(conn: &Connection) {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT
+        )",
+        [],
+    ).unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS patients (
+            cpf TEXT PRIMARY KEY,
+            nome TEXT,
+            birth_date TEXT,
+            birth_date_source TEXT
+        )",
+        [],
+    ).unwrap();
+    // Rows from before `birth_date_source` keep it NULL; see `duplicates::find`.
+    if conn.prepare("SELECT birth_date_source FROM patients LIMIT 0").is_err() {
+        conn.execute("ALTER TABLE patients ADD COLUMN birth_date_source TEXT", []).unwrap();
+    }
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS patient_aliases (
+            cpf TEXT PRIMARY KEY,
+            merged_into TEXT NOT NULL,
+            merged_at TEXT
+        )",
+        [],
+    ).unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS web_uploads (
+            filename TEXT PRIMARY KEY,
+            username TEXT NOT NULL,
+            uploaded_at TEXT
+        )",
+        [],
+    ).unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS duplicate_dismissals (
+            cpf_a TEXT,
+            cpf_b TEXT,
+            dismissed_at TEXT,
+            PRIMARY KEY (cpf_a, cpf_b)
+        )",
+        [],
+    ).unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS medications (
+            id INTEGER PRIMARY KEY,
+            name TEXT UNIQUE
+        )",
+        [],
+    ).unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS patient_medications (
+            cpf TEXT,
+            med_id INTEGER,
+            status TEXT,
+            dosage TEXT,
+            start_date TEXT,
+            end_date TEXT,
+            drug TEXT,
+            FOREIGN KEY(cpf) REFERENCES patients(cpf),
+            FOREIGN KEY(med_id) REFERENCES medications(id)
+        )",
+        [],
+    ).unwrap();
+    // `drug` holds the drug name, sealed, when the encrypted mode is on: the
+    // `med_id` link would tell which patient takes which drug in plain text.
+    if conn.prepare("SELECT drug FROM patient_medications LIMIT 0").is_err() {
+        conn.execute("ALTER TABLE patient_medications ADD COLUMN drug TEXT", []).unwrap();
+    }
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS doctors (
+            number TEXT NOT NULL,
+            uf TEXT NOT NULL,
+            nome TEXT,
+            especialidade TEXT,
+            updated_at TEXT,
+            PRIMARY KEY (number, uf)
+        )",
+        [],
+    ).unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS doctor_keys (
+            crm TEXT PRIMARY KEY,
+            public_key TEXT NOT NULL,
+            created_at TEXT
+        )",
+        [],
+    ).unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS ledger (
+            seq INTEGER PRIMARY KEY AUTOINCREMENT,
+            cpf TEXT NOT NULL,
+            tx_id TEXT NOT NULL,
+            filename TEXT,
+            timestamp TEXT,
+            hash TEXT NOT NULL,
+            previous TEXT NOT NULL,
+            recorded_at TEXT
+        )",
+        [],
+    ).unwrap();
+    // Only `filename` may change, and only to be cleared: older rows recorded
+    // the patient-named file, which an erasure must not leave behind.
+    conn.execute("DROP TRIGGER IF EXISTS ledger_append_only", []).unwrap();
+    conn.execute(
+        "CREATE TRIGGER IF NOT EXISTS ledger_sealed_columns BEFORE UPDATE OF seq, cpf, tx_id, timestamp, hash, previous, recorded_at ON ledger
+         BEGIN SELECT RAISE(ABORT, 'ledger is append-only'); END",
+        [],
+    ).unwrap();
+    conn.execute(
+        "CREATE TRIGGER IF NOT EXISTS ledger_filename_cleared BEFORE UPDATE OF filename ON ledger WHEN NEW.filename <> ''
+         BEGIN SELECT RAISE(ABORT, 'ledger is append-only'); END",
+        [],
+    ).unwrap();
+    conn.execute(
+        "CREATE TRIGGER IF NOT EXISTS ledger_no_delete BEFORE DELETE ON ledger
+         BEGIN SELECT RAISE(ABORT, 'ledger is append-only'); END",
+        [],
+    ).unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS access_grants (
+            id INTEGER PRIMARY KEY,
+            cpf TEXT NOT NULL,
+            crm TEXT NOT NULL,
+            granted_at TEXT,
+            expires_at TEXT,
+            revoked_at TEXT,
+            source TEXT
+        )",
+        [],
+    ).unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS users (
+            id INTEGER PRIMARY KEY,
+            username TEXT UNIQUE NOT NULL,
+            nome TEXT,
+            password_hash TEXT NOT NULL,
+            role TEXT NOT NULL,
+            crm TEXT,
+            created_at TEXT
+        )",
+        [],
+    ).unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            token TEXT PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            created_at TEXT,
+            expires_at TEXT,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        )",
+        [],
+    ).unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT NOT NULL,
+            action TEXT NOT NULL,
+            cpf TEXT,
+            route TEXT,
+            timestamp TEXT NOT NULL,
+            ip TEXT
+        )",
+        [],
+    ).unwrap();
+    conn.execute(
+        "CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+         BEGIN SELECT RAISE(ABORT, 'audit_log is append-only'); END",
+        [],
+    ).unwrap();
+    conn.execute(
+        "CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+         BEGIN SELECT RAISE(ABORT, 'audit_log is append-only'); END",
+        [],
+    ).unwrap();
+}
+
+/// Rewrites CPFs stored as `12345605700` or other spellings into the
+/// `000.000.000-00` form; returns the number of rows changed. When both
+/// forms were registered as patients, the row under the normalized CPF is
+/// kept. The ledger and the audit log are append-only and stay as written.
+pub fn normalize_cpfs(conn: &Connection) -> usize {
+    let mut changed = 0;
+    for (table, column) in [("patients", "cpf"), ("patient_medications", "cpf"), ("access_grants", "cpf"),
+                            ("patient_aliases", "cpf"), ("patient_aliases", "merged_into")] {
+        let stored: Vec<String> = conn.prepare(&format!("SELECT DISTINCT {} FROM {}", column, table)).unwrap()
+            .query_map([], |row| row.get(0)).unwrap()
+            .filter_map(|c| c.ok())
+            .collect();
+        for raw in stored {
+            let cpf = Cpf::normalize(&raw);
+            if cpf != raw {
+                changed += conn.execute(&format!("UPDATE OR IGNORE {} SET {} = ?2 WHERE {} = ?1", table, column, column), [&raw, &cpf]).unwrap();
+                changed += conn.execute(&format!("DELETE FROM {} WHERE {} = ?1", table, column), [&raw]).unwrap();
+            }
+        }
+    }
+    changed
+}
+
+/// Rewrites the CRMs of user accounts, access grants and public keys into the
+/// `10057-SP` form, and renames the private key files to match; returns the
+/// number of rows and files changed. Values that do not parse, such as a CRM
+/// without UF, are left for the admin to fix. A public key already stored
+/// under the normalized CRM is kept.
+pub fn normalize_crms(conn: &Connection) -> usize {
+    let mut changed = crate::signature::normalize_key_files();
+    for table in ["users", "access_grants", "doctor_keys"] {
+        let stored: Vec<String> = conn.prepare(&format!("SELECT DISTINCT crm FROM {} WHERE crm IS NOT NULL", table)).unwrap()
+            .query_map([], |row| row.get(0)).unwrap()
+            .filter_map(|c| c.ok())
+            .collect();
+        for raw in stored {
+            let crm = Crm::normalize(&raw);
+            if crm != raw {
+                changed += conn.execute(&format!("UPDATE OR IGNORE {} SET crm = ?2 WHERE crm = ?1", table), [&raw, &crm]).unwrap();
+            }
+        }
+    }
+    changed
+}
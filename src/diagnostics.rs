@@ -0,0 +1,217 @@
+use regex::Regex;
+use serde::Serialize;
+
+use crate::cpf::Cpf;
+use crate::crm::Crm;
+use crate::medfile;
+
+/// Header sections every .med needs, in the order the parser expects them.
+pub const REQUIRED_SECTIONS: &[&str] = &["PATIENT", "DOCTOR", "TRANSACTION", "CONTRACT_CONDITIONS"];
+
+pub const KNOWN_SECTIONS: &[&str] = &["PATIENT", "DOCTOR", "TRANSACTION", "CONTRACT_CONDITIONS", "CONTENT", "ANAMNESE",
+                                      "EXAME FISICO", "HIPOTESE DIAGNOSTICA", "CONDUTA", "ADENDO", "ASSINATURA"];
+
+/// Directives written as `!NOME` in the clinical sections.
+pub const DIRECTIVES: &[&str] = &["PRESCREVO", "AJUSTO", "SUSPENDO", "MANTENHO", "REDUZO", "AUMENTO", "ORIENTO", "SOLICITO",
+                                  "ENCAMINHO", "AGUARDO", "CONSIDERAR", "HPP", "HF", "MED", "EX", "RX", "RM", "LAB", "EEG", "LAUDO"];
+
+/// Directives that change the patient's medication list and need a drug name.
+pub const MEDICATION_DIRECTIVES: &[&str] = &["PRESCREVO", "AJUSTO", "SUSPENDO", "MANTENHO"];
+
+/// What a directive records, for grouping and coloring.
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectiveKind {
+    Medication,
+    Exam,
+    History,
+    Note,
+}
+
+pub fn directive_kind(name: &str) -> Option<DirectiveKind> {
+    match name.to_uppercase().as_str() {
+        "PRESCREVO" | "AJUSTO" | "SUSPENDO" | "MANTENHO" | "REDUZO" | "AUMENTO" => Some(DirectiveKind::Medication),
+        "SOLICITO" | "EX" | "RX" | "RM" | "LAB" | "EEG" | "LAUDO" => Some(DirectiveKind::Exam),
+        "HPP" | "HF" | "MED" => Some(DirectiveKind::History),
+        "ORIENTO" | "ENCAMINHO" | "AGUARDO" | "CONSIDERAR" => Some(DirectiveKind::Note),
+        _ => None,
+    }
+}
+
+const REQUIRED_FIELDS: &[(&str, &[&str])] = &[
+    ("PATIENT", &["CPF", "Nome", "Idade"]),
+    ("DOCTOR", &["CRM", "Nome", "Especialidade"]),
+    ("TRANSACTION", &["ID", "Timestamp"]),
+];
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a .med, located by 1-based line and 0-based character
+/// columns `start..end`.
+#[derive(Serialize, Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, line: usize, start: usize, end: usize, message: impl Into<String>) -> Diagnostic {
+        Diagnostic { severity, line, start, end, message: message.into() }
+    }
+
+    pub fn describe(&self) -> String {
+        let kind = match self.severity {
+            Severity::Error => "erro",
+            Severity::Warning => "aviso",
+        };
+        if self.line == 0 {
+            return format!("{}: {}", kind, self.message);
+        }
+        format!("{}:{}: {}: {}", self.line, self.start + 1, kind, self.message)
+    }
+}
+
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.severity == Severity::Error)
+}
+
+fn char_len(s: &str) -> usize {
+    s.chars().count()
+}
+
+/// Flags a CPF of the patient registry that fails the check digits; `line`
+/// is 0 since it does not come from a file.
+pub fn check_registered_cpf(cpf: &str, nome: &str) -> Option<Diagnostic> {
+    match Cpf::parse(cpf) {
+        Ok(parsed) if parsed.is_valid() => None,
+        Ok(_) => Some(Diagnostic::new(Severity::Warning, 0, 0, 0, format!("{} ({}): CPF com dígitos verificadores inválidos", cpf, nome))),
+        Err(e) => Some(Diagnostic::new(Severity::Error, 0, 0, 0, format!("{} ({}): {}", cpf, nome, e))),
+    }
+}
+
+/// Checks structure, header fields and directives of a .med file.
+pub fn check(content: &str) -> Vec<Diagnostic> {
+    let directive_re = Regex::new(r"!([A-Za-z]\w*)").unwrap();
+    let mut out = Vec::new();
+    let mut seen: Vec<(String, usize)> = Vec::new();
+    let mut current: Option<String> = None;
+    let mut fields: Vec<(String, String, usize)> = Vec::new();
+    let mut doctor_crm: Option<Crm> = None;
+    // CRM of [ASSINATURA] as (line, start, end, value).
+    let mut signed_crm: Option<(usize, usize, usize, String)> = None;
+
+    for (i, line) in content.lines().enumerate() {
+        let n = i + 1;
+        if let Some(name) = medfile::section_header(line) {
+            let start = char_len(line) - char_len(line.trim_start());
+            let end = char_len(line.trim_end());
+            if !KNOWN_SECTIONS.contains(&name) {
+                out.push(Diagnostic::new(Severity::Warning, n, start, end, format!("Seção desconhecida [{}]", name)));
+            } else if let Some((_, first)) = seen.iter().find(|(s, _)| s == name) {
+                out.push(Diagnostic::new(Severity::Warning, n, start, end, format!("Seção [{}] repetida (primeira na linha {})", name, first)));
+            }
+            seen.push((name.to_string(), n));
+            current = Some(name.to_string());
+            continue;
+        }
+        let Some(section) = current.as_deref() else {
+            if !line.trim().is_empty() {
+                out.push(Diagnostic::new(Severity::Warning, n, 0, char_len(line), "Texto fora de qualquer seção"));
+            }
+            continue;
+        };
+        if REQUIRED_FIELDS.iter().any(|(s, _)| *s == section) {
+            if let Some((key, value)) = line.split_once(':') {
+                fields.push((section.to_string(), key.trim().to_string(), n));
+                let value_start = char_len(key) + 1 + (char_len(value) - char_len(value.trim_start()));
+                let value_end = char_len(line.trim_end());
+                let value = value.trim();
+                match (section, key.trim()) {
+                    ("PATIENT", "CPF") => match Cpf::parse(value) {
+                        Err(e) => out.push(Diagnostic::new(Severity::Error, n, value_start, value_end, e)),
+                        Ok(cpf) if !cpf.is_valid() => out.push(Diagnostic::new(
+                            Severity::Warning, n, value_start, value_end, "CPF com dígitos verificadores inválidos")),
+                        Ok(cpf) if &*cpf != value => out.push(Diagnostic::new(
+                            Severity::Warning, n, value_start, value_end, "CPF fora do formato 000.000.000-00")),
+                        Ok(_) => {}
+                    },
+                    ("DOCTOR", "CRM") => match Crm::parse(value) {
+                        Err(e) => out.push(Diagnostic::new(Severity::Error, n, value_start, value_end, e)),
+                        Ok(crm) => {
+                            if &*crm != value {
+                                out.push(Diagnostic::new(Severity::Warning, n, value_start, value_end, "CRM fora do formato 00000-UF"));
+                            }
+                            doctor_crm = Some(crm);
+                        }
+                    },
+                    ("PATIENT", "Idade") if value.parse::<u32>().is_err() => out.push(Diagnostic::new(
+                        Severity::Error, n, value_start, value_end, "Idade deve ser um número inteiro")),
+                    ("TRANSACTION", "Timestamp") if chrono::DateTime::parse_from_rfc3339(value).is_err() => out.push(Diagnostic::new(
+                        Severity::Error, n, value_start, value_end, "Timestamp inválido; use RFC 3339, ex.: 2025-10-15T10:00:00Z")),
+                    _ => {}
+                }
+            }
+            continue;
+        }
+        if section == "ASSINATURA"
+            && let Some((key, value)) = line.split_once(':')
+            && key.trim().eq_ignore_ascii_case("CRM") {
+            let start = char_len(key) + 1 + (char_len(value) - char_len(value.trim_start()));
+            signed_crm = Some((n, start, char_len(line.trim_end()), value.trim().to_string()));
+            continue;
+        }
+        for cap in directive_re.captures_iter(line) {
+            let name = cap.get(1).unwrap();
+            let upper = name.as_str().to_uppercase();
+            let start = char_len(&line[..cap.get(0).unwrap().start()]);
+            let end = start + 1 + char_len(name.as_str());
+            if !DIRECTIVES.contains(&upper.as_str()) {
+                out.push(Diagnostic::new(Severity::Warning, n, start, end, format!("Diretiva desconhecida !{}", upper)));
+            } else if MEDICATION_DIRECTIVES.contains(&upper.as_str()) {
+                let rest = line[name.end()..].split(';').next().unwrap_or("").trim();
+                if rest.is_empty() || rest == "[]" {
+                    out.push(Diagnostic::new(Severity::Error, n, start, end, format!("!{} sem o nome do medicamento", upper)));
+                }
+            }
+        }
+    }
+
+    let positions: Vec<Option<usize>> = REQUIRED_SECTIONS.iter().map(|s| seen.iter().find(|(name, _)| name == s).map(|(_, l)| *l)).collect();
+    for (section, position) in REQUIRED_SECTIONS.iter().zip(&positions) {
+        if position.is_none() {
+            out.push(Diagnostic::new(Severity::Error, 1, 0, 0, format!("Seção obrigatória [{}] ausente", section)));
+        }
+    }
+    let present: Vec<usize> = positions.iter().flatten().copied().collect();
+    if present.windows(2).any(|w| w[0] > w[1]) {
+        out.push(Diagnostic::new(Severity::Error, 1, 0, 0,
+                                 "Seções de cabeçalho fora de ordem; use [PATIENT], [DOCTOR], [TRANSACTION], [CONTRACT_CONDITIONS]"));
+    }
+    for (section, keys) in REQUIRED_FIELDS {
+        let Some((_, line)) = seen.iter().find(|(name, _)| name == section) else { continue };
+        for key in *keys {
+            if !fields.iter().any(|(s, k, _)| s == section && k == key) {
+                let severity = if *key == "ID" { Severity::Warning } else { Severity::Error };
+                out.push(Diagnostic::new(severity, *line, 0, char_len(section) + 2, format!("Campo {} ausente em [{}]", key, section)));
+            }
+        }
+    }
+    if let (Some(crm), Some((n, start, end, signed))) = (&doctor_crm, &signed_crm)
+        && !crm.refers_to(signed) {
+        out.push(Diagnostic::new(Severity::Warning, *n, *start, *end,
+                                 format!("CRM da [ASSINATURA] ({}) difere do [DOCTOR] ({})", signed, crm)));
+    }
+    if !has_errors(&out) && medfile::parse_med_file(content).is_none() {
+        out.push(Diagnostic::new(Severity::Error, 1, 0, 0, "Arquivo não pôde ser interpretado"));
+    }
+    out.sort_by_key(|d| (d.line, d.start));
+    out
+}
@@ -0,0 +1,54 @@
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::crm::Crm;
+use crate::medfile::Consultation;
+
+/// A row of `doctors`, keyed by CRM number and UF.
+#[derive(Serialize, Clone)]
+pub struct DoctorRecord {
+    pub crm: String,
+    pub number: String,
+    pub uf: String,
+    pub nome: String,
+    pub especialidade: String,
+}
+
+fn record_from_row(row: &rusqlite::Row) -> rusqlite::Result<DoctorRecord> {
+    let number: String = row.get(0)?;
+    let uf: String = row.get(1)?;
+    Ok(DoctorRecord { crm: format!("{}-{}", number, uf), number, uf, nome: row.get(2)?, especialidade: row.get(3)? })
+}
+
+/// Registers the doctors of `consultations` with the name and specialty of
+/// their latest one, and the doctors with a user account that have none.
+/// [DOCTOR] blocks whose CRM does not parse are left out (`mymed check`
+/// reports them).
+pub fn sync(conn: &Connection, consultations: &[Consultation]) {
+    let mut sorted: Vec<&Consultation> = consultations.iter().collect();
+    sorted.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    for cons in sorted {
+        let Ok(crm) = Crm::parse(&cons.doctor.crm) else { continue };
+        conn.execute("INSERT OR REPLACE INTO doctors (number, uf, nome, especialidade, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                     [crm.number(), crm.uf(), &cons.doctor.nome, &cons.doctor.especialidade, &cons.timestamp]).unwrap();
+    }
+    let accounts: Vec<(String, String)> = conn.prepare("SELECT crm, nome FROM users WHERE crm IS NOT NULL").unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get::<_, Option<String>>(1)?.unwrap_or_default()))).unwrap()
+        .filter_map(|a| a.ok())
+        .collect();
+    for (crm, nome) in accounts {
+        let Ok(crm) = Crm::parse(&crm) else { continue };
+        conn.execute("INSERT OR IGNORE INTO doctors (number, uf, nome, especialidade) VALUES (?1, ?2, ?3, '')",
+                     [crm.number(), crm.uf(), &nome]).unwrap();
+    }
+}
+
+pub fn all(conn: &Connection) -> Vec<DoctorRecord> {
+    let mut stmt = conn.prepare("SELECT number, uf, nome, especialidade FROM doctors ORDER BY nome").unwrap();
+    stmt.query_map([], record_from_row).unwrap().filter_map(|d| d.ok()).collect()
+}
+
+pub fn get(conn: &Connection, crm: &Crm) -> Option<DoctorRecord> {
+    conn.query_row("SELECT number, uf, nome, especialidade FROM doctors WHERE number = ?1 AND uf = ?2",
+                   [crm.number(), crm.uf()], record_from_row).optional().unwrap()
+}
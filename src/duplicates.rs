@@ -0,0 +1,206 @@
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::cpf::Cpf;
+use crate::medfile::{self, Patient};
+use crate::patients;
+
+/// Pairs scoring below this are not reported by default.
+pub const MIN_SCORE: f64 = 0.75;
+/// Pairs whose names are less alike than this are never compared further:
+/// the same person may have had the CPF or birth date mistyped, but is
+/// still filed under (nearly) the same name.
+const MIN_NAME: f64 = 0.85;
+/// Name particles that are often dropped or added between records.
+const PARTICLES: &[&str] = &["da", "das", "de", "do", "dos", "e"];
+
+/// One side of a candidate pair.
+#[derive(Serialize, Clone)]
+pub struct Record {
+    #[serde(flatten)]
+    pub patient: Patient,
+    /// Whether name and birth date were registered by a person; otherwise they
+    /// come from the .med files, where the birth date is estimated from the age,
+    /// even if `medications::sync` copied them into `patients`.
+    pub registered: bool,
+    pub consultations: usize,
+}
+
+/// Two patients that may be the same person filed twice.
+#[derive(Serialize)]
+pub struct Candidate {
+    pub a: Record,
+    pub b: Record,
+    /// Weighted sum of the three scores below, from 0 to 1.
+    pub score: f64,
+    pub name: f64,
+    pub birth_date: f64,
+    /// Digits that differ between the CPFs, counting a swap of neighbours as one.
+    pub cpf_distance: usize,
+    pub reasons: Vec<String>,
+}
+
+impl Candidate {
+    /// `(kept, merged)` as suggested to the reviewer: a CPF with valid check
+    /// digits first, then a registered patient, then the longer history.
+    pub fn suggestion(&self) -> (&Record, &Record) {
+        let rank = |r: &Record| (Cpf::parse(&r.patient.cpf).is_ok_and(|c| c.is_valid()), r.registered, r.consultations);
+        if rank(&self.b) > rank(&self.a) { (&self.b, &self.a) } else { (&self.a, &self.b) }
+    }
+}
+
+fn tokens(nome: &str) -> Vec<String> {
+    medfile::fold(nome)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty() && !PARTICLES.contains(t))
+        .map(String::from)
+        .collect()
+}
+
+/// Numbers in a name ("Filho 2") have to match exactly.
+fn token_similarity(a: &str, b: &str) -> f64 {
+    if a.chars().chain(b.chars()).any(|c| c.is_ascii_digit()) {
+        return if a == b { 1.0 } else { 0.0 };
+    }
+    strsim::jaro_winkler(a, b)
+}
+
+/// How alike two names are, from 0 to 1, ignoring case, accents and
+/// particles: each word of the shorter name is matched to the closest word of
+/// the longer one, so `Cleuza F. de Sa` still scores high against
+/// `Cleusa Ferreira de Sá`, and missing words cost a little.
+pub fn name_similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (tokens(a), tokens(b));
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    if short.is_empty() {
+        return 0.0;
+    }
+    let covered: f64 = short.iter()
+        .map(|t| long.iter().map(|u| token_similarity(t, u)).fold(0.0, f64::max))
+        .sum();
+    covered / short.len() as f64 * (0.8 + 0.2 * short.len() as f64 / long.len() as f64)
+}
+
+/// 1 for the same date, 0.6 for a typo (one character, or day and month
+/// swapped). Dates estimated from the age only tell the year, so they score
+/// 0.5 when the years are at most one apart.
+fn birth_similarity(a: &Record, b: &Record) -> (f64, Option<&'static str>) {
+    let (da, db) = (&a.patient.birth_date, &b.patient.birth_date);
+    if !(a.registered && b.registered) {
+        let year = |d: &str| d.get(..4).and_then(|y| y.parse::<i32>().ok());
+        return match (year(da), year(db)) {
+            (Some(ya), Some(yb)) if (ya - yb).abs() <= 1 => (0.5, Some("idade compatível")),
+            _ => (0.0, None),
+        };
+    }
+    if da == db {
+        return (1.0, Some("nascimento igual"));
+    }
+    let swapped = |d: &str| d.get(..4).zip(d.get(5..7)).zip(d.get(8..10)).map(|((y, m), d)| format!("{}-{}-{}", y, d, m));
+    if strsim::levenshtein(da, db) == 1 || swapped(da).as_deref() == Some(db.as_str()) {
+        return (0.6, Some("nascimento com provável erro de digitação"));
+    }
+    (0.0, None)
+}
+
+fn cpf_distance(a: &str, b: &str) -> usize {
+    let digits = |s: &str| s.chars().filter(|c| c.is_ascii_digit()).collect::<String>();
+    strsim::damerau_levenshtein(&digits(a), &digits(b))
+}
+
+fn score(a: &Record, b: &Record) -> Option<Candidate> {
+    let name = name_similarity(&a.patient.nome, &b.patient.nome);
+    if name < MIN_NAME {
+        return None;
+    }
+    let (birth_date, birth_reason) = birth_similarity(a, b);
+    let cpf_distance = cpf_distance(&a.patient.cpf, &b.patient.cpf);
+    let cpf = match cpf_distance {
+        0 | 1 => 1.0,
+        2 => 0.6,
+        3 => 0.2,
+        _ => 0.0,
+    };
+    let mut reasons = vec![format!("nome {:.2}", name)];
+    reasons.extend(birth_reason.map(String::from));
+    if cpf > 0.0 {
+        reasons.push(format!("CPF difere em {} dígito(s)", cpf_distance));
+    }
+    let round = |x: f64| (x * 100.0).round() / 100.0;
+    Some(Candidate {
+        a: a.clone(),
+        b: b.clone(),
+        score: round(0.5 * name + 0.3 * birth_date + 0.2 * cpf),
+        name: round(name),
+        birth_date,
+        cpf_distance,
+        reasons,
+    })
+}
+
+/// Ordered key of a pair in `duplicate_dismissals`.
+fn pair<'a>(a: &'a str, b: &'a str) -> (&'a str, &'a str) {
+    if a <= b { (a, b) } else { (b, a) }
+}
+
+fn dismissed(conn: &Connection) -> HashSet<(String, String)> {
+    let mut stmt = conn.prepare("SELECT cpf_a, cpf_b FROM duplicate_dismissals").unwrap();
+    stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().filter_map(|p| p.ok()).collect()
+}
+
+/// Records that a reviewer found `a` and `b` to be different people, so the
+/// pair is not reported again.
+pub fn dismiss(conn: &Connection, a: &str, b: &str) {
+    let (a, b) = pair(a, b);
+    conn.execute("INSERT OR REPLACE INTO duplicate_dismissals (cpf_a, cpf_b, dismissed_at) VALUES (?1, ?2, ?3)",
+                 [a, b, &chrono::Utc::now().to_rfc3339()]).unwrap();
+}
+
+/// Forgets the dismissals involving `cpf`, once it was merged or erased.
+pub fn forget(conn: &Connection, cpf: &str) -> usize {
+    conn.execute("DELETE FROM duplicate_dismissals WHERE cpf_a = ?1 OR cpf_b = ?1", [cpf]).unwrap()
+}
+
+/// Pairs of patients, registered or only in the .med files, scoring at least
+/// `min_score`, best first. CPFs already merged are resolved by
+/// `load_consultations`, and dismissed pairs are left out.
+pub fn find(conn: &Connection, min_score: f64) -> Vec<Candidate> {
+    let sources = patients::birth_date_sources(conn);
+    let mut consultations: HashMap<String, usize> = HashMap::new();
+    for cons in medfile::load_consultations() {
+        *consultations.entry(cons.patient.cpf).or_default() += 1;
+    }
+    // Rows written before the source was recorded: `medications::sync` used
+    // to register every patient of the .med files, so a row with
+    // consultations most likely holds an estimated date.
+    let registered = |cpf: &str| match sources.get(cpf) {
+        Some(Some(source)) => source == patients::FROM_REGISTRY,
+        Some(None) => !consultations.contains_key(cpf),
+        None => false,
+    };
+    let records: Vec<Record> = patients::search(conn, "").into_iter()
+        .map(|patient| Record {
+            registered: registered(&patient.cpf),
+            consultations: consultations.get(&patient.cpf).copied().unwrap_or(0),
+            patient,
+        })
+        .collect();
+    let dismissed = dismissed(conn);
+
+    let mut found = Vec::new();
+    for (i, a) in records.iter().enumerate() {
+        for b in &records[i + 1..] {
+            let (x, y) = pair(&a.patient.cpf, &b.patient.cpf);
+            if dismissed.contains(&(x.to_string(), y.to_string())) {
+                continue;
+            }
+            if let Some(candidate) = score(a, b)
+                && candidate.score >= min_score {
+                found.push(candidate);
+            }
+        }
+    }
+    found.sort_by(|a, b| b.score.total_cmp(&a.score));
+    found
+}
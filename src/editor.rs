@@ -1,16 +1,409 @@
 use clap::Parser;
+use crossterm::cursor::MoveTo;
+use crossterm::event::{read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::terminal::{self, disable_raw_mode, enable_raw_mode, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use mymed::complete::{self, Context};
+use mymed::consult::{self, Chart};
+use mymed::cpf::Cpf;
+use mymed::crm::Crm;
+use mymed::medfile::{self, Doctor};
+use mymed::cli::wrap;
+use mymed::{audit, db, diagnostics};
+use regex::Regex;
+use rusqlite::Connection;
+use std::io::{stdout, Write};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "mymed_editor")]
 struct Cli {
     #[arg(long)]
-    patient_cpf: String,
+    patient_cpf: Cpf,
     #[arg(long)]
-    doctor_crm: String,
+    doctor_crm: Crm,
+}
+
+/// Width of the side panel with medications and the last consultation.
+const PANEL_WIDTH: usize = 36;
+const HELP: &str = "Ctrl-S salvar  Ctrl-T modelo  Tab completar  Ctrl-Q sair";
+
+/// Candidates offered for the token that starts at `start` on line `row`.
+struct Completion {
+    row: usize,
+    start: usize,
+    candidates: Vec<(String, Option<String>)>,
+    index: usize,
+}
+
+struct Editor {
+    conn: Connection,
+    files: Vec<(PathBuf, String)>,
+    chart: Chart,
+    doctor: Doctor,
+    lines: Vec<String>,
+    row: usize,
+    col: usize,
+    top: usize,
+    left: usize,
+    dirty: bool,
+    status: String,
+    completion: Option<Completion>,
+    /// Template applied by the next Ctrl-T.
+    template: usize,
+    quit_pending: bool,
+}
+
+fn char_len(s: &str) -> usize {
+    s.chars().count()
+}
+
+fn byte_at(s: &str, col: usize) -> usize {
+    s.char_indices().nth(col).map_or(s.len(), |(i, _)| i)
+}
+
+impl Editor {
+    fn content(&self) -> String {
+        format!("{}\n", self.lines.join("\n").trim_end())
+    }
+
+    fn section_at(&self, row: usize) -> Option<&str> {
+        self.lines[..=row].iter().rev().find_map(|l| medfile::section_header(l))
+    }
+
+    fn panel(&self) -> Vec<(String, bool)> {
+        let width = PANEL_WIDTH - 2;
+        let mut out = vec![
+            ("PACIENTE".to_string(), true),
+            (self.chart.patient.nome.clone(), false),
+            (format!("{} - {} anos", self.chart.patient.cpf, medfile::calculate_age(&self.chart.patient.birth_date)), false),
+            (String::new(), false),
+            ("MEDICAÇÕES EM USO".to_string(), true),
+        ];
+        if self.chart.medications.is_empty() {
+            out.push(("(nenhuma)".to_string(), false));
+        }
+        for (name, dosage) in &self.chart.medications {
+            out.extend(wrap(&format!("- {} {}", name, dosage), width).into_iter().map(|l| (l, false)));
+        }
+        out.push((String::new(), false));
+        out.push(("ÚLTIMA CONSULTA".to_string(), true));
+        match self.chart.last() {
+            Some(last) => {
+                out.push((last.timestamp.get(..10).unwrap_or(&last.timestamp).to_string(), false));
+                out.extend(wrap(&format!("{} ({})", last.doctor.nome, last.doctor.especialidade), width).into_iter().map(|l| (l, false)));
+                for (title, text) in [("Hipótese:", &last.hipotese_diagnostica), ("Conduta:", &last.conduta)] {
+                    out.push((title.to_string(), true));
+                    out.extend(wrap(text, width).into_iter().map(|l| (l, false)));
+                }
+            }
+            None => out.push(("(nenhuma)".to_string(), false)),
+        }
+        out
+    }
+
+    fn draw(&mut self) -> std::io::Result<()> {
+        let (cols, rows) = terminal::size()?;
+        let (cols, rows) = (cols as usize, rows as usize);
+        let show_panel = cols > PANEL_WIDTH + 40;
+        let width = if show_panel { cols - PANEL_WIDTH - 1 } else { cols };
+        let height = rows.saturating_sub(2).max(1);
+        if self.row < self.top {
+            self.top = self.row;
+        } else if self.row >= self.top + height {
+            self.top = self.row + 1 - height;
+        }
+        if self.col < self.left {
+            self.left = self.col;
+        } else if self.col >= self.left + width {
+            self.left = self.col + 1 - width;
+        }
+
+        let mut out = stdout();
+        queue!(out, Clear(ClearType::All))?;
+        for (y, line) in self.lines.iter().skip(self.top).take(height).enumerate() {
+            let visible: String = line.chars().skip(self.left).take(width).collect();
+            queue!(out, MoveTo(0, y as u16))?;
+            if medfile::section_header(line).is_some() {
+                queue!(out, SetForegroundColor(Color::Cyan), SetAttribute(Attribute::Bold), Print(visible), SetAttribute(Attribute::Reset), ResetColor)?;
+            } else {
+                queue!(out, Print(visible))?;
+            }
+        }
+        if show_panel {
+            let x = (width + 1) as u16;
+            for y in 0..height {
+                queue!(out, MoveTo(width as u16, y as u16), SetForegroundColor(Color::DarkGrey), Print("│"), ResetColor)?;
+            }
+            for (y, (text, title)) in self.panel().into_iter().take(height).enumerate() {
+                let text: String = text.chars().take(PANEL_WIDTH - 1).collect();
+                queue!(out, MoveTo(x, y as u16))?;
+                if title {
+                    queue!(out, SetAttribute(Attribute::Bold), Print(text), SetAttribute(Attribute::Reset))?;
+                } else {
+                    queue!(out, Print(text))?;
+                }
+            }
+        }
+        let status: String = self.status.chars().take(cols).collect();
+        let help: String = format!("{}{}", HELP, if self.dirty { "  [modificado]" } else { "" }).chars().take(cols).collect();
+        queue!(out, MoveTo(0, height as u16), SetAttribute(Attribute::Reverse), Print(format!("{:<width$}", status, width = cols)),
+               SetAttribute(Attribute::Reset), MoveTo(0, height as u16 + 1), SetForegroundColor(Color::DarkGrey), Print(help), ResetColor)?;
+        queue!(out, MoveTo((self.col - self.left) as u16, (self.row - self.top) as u16))?;
+        out.flush()
+    }
+
+    fn insert(&mut self, text: &str) {
+        let at = byte_at(&self.lines[self.row], self.col);
+        self.lines[self.row].insert_str(at, text);
+        self.col += char_len(text);
+        self.dirty = true;
+    }
+
+    fn newline(&mut self) {
+        let at = byte_at(&self.lines[self.row], self.col);
+        let rest = self.lines[self.row].split_off(at);
+        self.lines.insert(self.row + 1, rest);
+        self.row += 1;
+        self.col = 0;
+        self.dirty = true;
+    }
+
+    fn backspace(&mut self) {
+        if self.col > 0 {
+            let at = byte_at(&self.lines[self.row], self.col - 1);
+            self.lines[self.row].remove(at);
+            self.col -= 1;
+        } else if self.row > 0 {
+            let line = self.lines.remove(self.row);
+            self.row -= 1;
+            self.col = char_len(&self.lines[self.row]);
+            self.lines[self.row].push_str(&line);
+        } else {
+            return;
+        }
+        self.dirty = true;
+    }
+
+    fn delete(&mut self) {
+        if self.col < char_len(&self.lines[self.row]) {
+            let at = byte_at(&self.lines[self.row], self.col);
+            self.lines[self.row].remove(at);
+        } else if self.row + 1 < self.lines.len() {
+            let next = self.lines.remove(self.row + 1);
+            self.lines[self.row].push_str(&next);
+        } else {
+            return;
+        }
+        self.dirty = true;
+    }
+
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.row = row.min(self.lines.len() - 1);
+        self.col = col.min(char_len(&self.lines[self.row]));
+    }
+
+    /// Replaces the clinical sections with the next built-in template.
+    fn apply_template(&mut self) {
+        let templates = consult::templates(&self.chart);
+        let (name, sections) = &templates[self.template % templates.len()];
+        self.template += 1;
+        let Some(content_line) = self.lines.iter().position(|l| medfile::section_header(l) == Some("CONTENT")) else {
+            self.status = "Seção [CONTENT] não encontrada.".to_string();
+            return;
+        };
+        let sections: Vec<(&str, &str)> = sections.iter().map(|(n, t)| (*n, t.as_str())).collect();
+        self.lines.truncate(content_line + 1);
+        self.lines.extend(medfile::clinical_sections(&sections).lines().map(String::from));
+        let first = content_line + 2;
+        self.move_to(first, 0);
+        self.dirty = true;
+        self.status = format!("Modelo: {} (Ctrl-T para o próximo)", name);
+    }
+
+    /// Completes the token before the cursor: `!` directives, `@` exam
+    /// codes, drug names after a medication directive and CID-10 codes in
+    /// [HIPOTESE DIAGNOSTICA]. Repeated Tab cycles through the candidates.
+    fn complete(&mut self) {
+        if let Some(c) = &mut self.completion
+            && c.row == self.row
+            && self.col == c.start + char_len(&c.candidates[c.index].0)
+        {
+            let at = byte_at(&self.lines[self.row], c.start);
+            let end = byte_at(&self.lines[self.row], self.col);
+            c.index = (c.index + 1) % c.candidates.len();
+            self.lines[self.row].replace_range(at..end, &c.candidates[c.index].0);
+            self.col = c.start + char_len(&c.candidates[c.index].0);
+            self.status = describe_completion(c);
+            return;
+        }
+
+        let line: Vec<char> = self.lines[self.row].chars().collect();
+        let mut start = self.col;
+        while start > 0 && (line[start - 1].is_alphanumeric() || matches!(line[start - 1], '.' | '_')) {
+            start -= 1;
+        }
+        let token: String = line[start..self.col].iter().collect();
+        let before: String = line[..start].iter().collect();
+        let drug_re = Regex::new(r"(?i)!(PRESCREVO|AJUSTO|SUSPENDO|MANTENHO)\s*\[?$").unwrap();
+        let ctx = match line[..start].last() {
+            Some('!') => Context::Directive,
+            Some('@') => Context::Exam,
+            _ if drug_re.is_match(&before) => Context::Drug,
+            _ if !token.is_empty() && self.section_at(self.row) == Some("HIPOTESE DIAGNOSTICA") => Context::Cid,
+            _ => {
+                self.status = "Nada para completar aqui.".to_string();
+                return;
+            }
+        };
+        let candidates: Vec<(String, Option<String>)> = complete::complete(&self.conn, &self.files, Some(&self.doctor.crm), ctx, &token)
+            .into_iter().map(|c| (c.label, c.detail)).collect();
+        if candidates.is_empty() {
+            self.status = format!("Sem sugestões para \"{}\".", token);
+            return;
+        }
+        let at = byte_at(&self.lines[self.row], start);
+        let end = byte_at(&self.lines[self.row], self.col);
+        self.lines[self.row].replace_range(at..end, &candidates[0].0);
+        self.col = start + char_len(&candidates[0].0);
+        self.dirty = true;
+        let c = Completion { row: self.row, start, candidates, index: 0 };
+        self.status = describe_completion(&c);
+        self.completion = Some(c);
+    }
+
+    /// Validates the text and stores it; returns the message to print on
+    /// success.
+    fn save(&mut self) -> Option<String> {
+        let content = self.content();
+        let found = diagnostics::check(&content);
+        if let Some(first) = found.iter().find(|d| d.severity == diagnostics::Severity::Error) {
+            let errors = found.iter().filter(|d| d.severity == diagnostics::Severity::Error).count();
+            self.status = format!("Não salvo: {}{}", first.describe(), if errors > 1 { format!(" (+{} erros)", errors - 1) } else { String::new() });
+            self.move_to(first.line.saturating_sub(1), first.start);
+            return None;
+        }
+        if let Err(e) = consult::check_new(&content, &self.chart.patient.cpf) {
+            self.status = format!("Não salvo: {}", e);
+            return None;
+        }
+        match consult::save(&self.conn, &content, &audit::cli_user(), "mymed_editor", None) {
+            Ok((filename, signed)) => {
+                let mut message = format!("Consulta salva em {}{}.", filename, if signed { ", assinada e selada no ledger" } else { "" });
+                for d in &found {
+                    message.push_str(&format!("\n{}", d.describe()));
+                }
+                Some(message)
+            }
+            Err(e) => {
+                self.status = format!("Falha ao salvar: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Handles one key; returns `Some(message)` when the editor should close.
+    fn key(&mut self, key: KeyEvent) -> Option<Option<String>> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        if !(ctrl && key.code == KeyCode::Char('q')) {
+            self.quit_pending = false;
+        }
+        if key.code != KeyCode::Tab {
+            self.completion = None;
+        }
+        match key.code {
+            KeyCode::Char('s') if ctrl => return self.save().map(Some),
+            KeyCode::Char('q') if ctrl => {
+                if !self.dirty || self.quit_pending {
+                    return Some(None);
+                }
+                self.quit_pending = true;
+                self.status = "Alterações não salvas. Ctrl-Q de novo para sair sem salvar.".to_string();
+            }
+            KeyCode::Char('t') if ctrl => self.apply_template(),
+            KeyCode::Char(c) if !ctrl => self.insert(&c.to_string()),
+            KeyCode::Tab => self.complete(),
+            KeyCode::Enter => self.newline(),
+            KeyCode::Backspace => self.backspace(),
+            KeyCode::Delete => self.delete(),
+            KeyCode::Left if self.col > 0 => self.col -= 1,
+            KeyCode::Left if self.row > 0 => self.move_to(self.row - 1, usize::MAX),
+            KeyCode::Right if self.col < char_len(&self.lines[self.row]) => self.col += 1,
+            KeyCode::Right if self.row + 1 < self.lines.len() => self.move_to(self.row + 1, 0),
+            KeyCode::Up if self.row > 0 => self.move_to(self.row - 1, self.col),
+            KeyCode::Down => self.move_to(self.row + 1, self.col),
+            KeyCode::Home => self.col = 0,
+            KeyCode::End => self.col = char_len(&self.lines[self.row]),
+            KeyCode::PageUp => self.move_to(self.row.saturating_sub(20), self.col),
+            KeyCode::PageDown => self.move_to(self.row + 20, self.col),
+            _ => {}
+        }
+        None
+    }
+}
+
+fn describe_completion(c: &Completion) -> String {
+    let (label, detail) = &c.candidates[c.index];
+    format!("{} {}({}/{})", label, detail.as_ref().map_or(String::new(), |d| format!("- {} ", d)), c.index + 1, c.candidates.len())
+}
+
+fn run(editor: &mut Editor) -> std::io::Result<Option<String>> {
+    loop {
+        editor.draw()?;
+        if let Event::Key(key) = read()?
+            && key.kind == KeyEventKind::Press
+            && let Some(done) = editor.key(key)
+        {
+            return Ok(done);
+        }
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
-    // TODO: load patient, open editor for .med
-    println!("Editor for patient {} by doctor {}", cli.patient_cpf, cli.doctor_crm);
-}
\ No newline at end of file
+    db::setup_db();
+    let conn = db::open();
+    let Some(chart) = consult::chart(&conn, &cli.patient_cpf) else {
+        eprintln!("Paciente não encontrado.");
+        std::process::exit(1);
+    };
+    let Some(doctor) = consult::doctor(&conn, &cli.doctor_crm) else {
+        eprintln!("Médico com CRM {} não encontrado.", cli.doctor_crm);
+        std::process::exit(1);
+    };
+    let content = medfile::consultation_template(&chart.patient, &doctor, &medfile::new_tx_id(), &[]);
+    let lines: Vec<String> = content.lines().map(String::from).collect();
+    let first = lines.iter().position(|l| medfile::section_header(l) == Some("ANAMNESE")).map_or(0, |i| i + 1);
+    let status = if doctor.especialidade.is_empty() { "Informe a especialidade em [DOCTOR].".to_string() } else { String::new() };
+    let mut editor = Editor {
+        conn,
+        files: medfile::load_med_files(),
+        chart,
+        doctor,
+        lines,
+        row: first,
+        col: 0,
+        top: 0,
+        left: 0,
+        dirty: false,
+        status,
+        completion: None,
+        template: 1,
+        quit_pending: false,
+    };
+
+    enable_raw_mode().unwrap();
+    execute!(stdout(), EnterAlternateScreen).unwrap();
+    let result = run(&mut editor);
+    execute!(stdout(), LeaveAlternateScreen).unwrap();
+    disable_raw_mode().unwrap();
+    match result {
+        Ok(Some(message)) => println!("{}", message),
+        Ok(None) => println!("Consulta descartada."),
+        Err(e) => {
+            eprintln!("Erro no terminal: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use rand::RngCore;
+use rand::rngs::OsRng;
+use regex::Regex;
+use rusqlite::Connection;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+use crate::medfile::{self, Exam};
+use crate::{patients, storage};
+
+/// Name particles that are not scrubbed from free text on their own.
+const NAME_PARTICLES: &[&str] = &["DE", "DA", "DO", "DAS", "DOS", "DR", "DRA"];
+
+#[derive(Serialize)]
+pub struct MedicationEvent {
+    pub action: String,
+    pub name: String,
+    pub dosage: String,
+}
+
+#[derive(Serialize)]
+pub struct AnonymizedConsultation {
+    pub patient: String,
+    pub age_group: String,
+    pub doctor: String,
+    pub especialidade: String,
+    pub date: String,
+    pub anamnese: String,
+    pub hipotese_diagnostica: String,
+    pub medications: Vec<MedicationEvent>,
+    pub exams: Vec<Exam>,
+}
+
+/// Secret mixed into every pseudonym so they are stable across exports but
+/// cannot be recomputed from a CPF by the recipient.
+fn pseudonym_secret(conn: &Connection) -> String {
+    storage::setting(conn, "pseudonym_secret").unwrap_or_else(|| {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let secret = hex::encode(bytes);
+        storage::set_setting(conn, "pseudonym_secret", &secret);
+        secret
+    })
+}
+
+fn digest(secret: &str, value: &str) -> [u8; 32] {
+    Sha256::digest(format!("{}:{}", secret, value).as_bytes()).into()
+}
+
+pub fn pseudonym(secret: &str, prefix: &str, value: &str) -> String {
+    format!("{}-{}", prefix, &hex::encode(digest(secret, value))[..12])
+}
+
+/// Per-patient shift between -182 and +182 days, applied to all their dates
+/// so intervals between visits are kept.
+fn date_shift(secret: &str, cpf: &str) -> chrono::Duration {
+    let d = digest(secret, &format!("shift:{}", cpf));
+    let n = u16::from_be_bytes([d[0], d[1]]) as i64;
+    chrono::Duration::days(n % 365 - 182)
+}
+
+pub fn age_group(age: i64) -> String {
+    if age >= 90 {
+        return "90+".to_string();
+    }
+    let low = age / 10 * 10;
+    format!("{}-{}", low, low + 9)
+}
+
+fn shift_timestamp(timestamp: &str, shift: chrono::Duration) -> String {
+    chrono::DateTime::parse_from_rfc3339(timestamp)
+        .map(|t| (t.date_naive() + shift).format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+/// Shifts the partial dates used in exam lines (`07/2025`, `2024`).
+fn shift_partial_date(date: &str, shift: chrono::Duration) -> String {
+    if let Ok(d) = chrono::NaiveDate::parse_from_str(&format!("15/{}", date), "%d/%m/%Y") {
+        return (d + shift).format("%m/%Y").to_string();
+    }
+    if let Ok(year) = date.parse::<i32>()
+        && let Some(d) = chrono::NaiveDate::from_ymd_opt(year, 7, 1) {
+        return (d + shift).format("%Y").to_string();
+    }
+    String::new()
+}
+
+/// Removes CPF-like numbers and the given people's names from free text.
+pub fn scrub(text: &str, names: &[&str]) -> String {
+    let cpf_re = Regex::new(r"\b\d{3}\.?\d{3}\.?\d{3}-?\d{2}\b").unwrap();
+    let mut out = cpf_re.replace_all(text, "[CPF]").into_owned();
+    for name in names {
+        let full = Regex::new(&format!(r"(?i)\b{}\b", regex::escape(name.trim()))).unwrap();
+        out = full.replace_all(&out, "[NOME]").into_owned();
+        for token in name.split_whitespace() {
+            let token = token.trim_matches('.');
+            if token.chars().count() < 3 || token.chars().any(|c| c.is_ascii_digit())
+                || NAME_PARTICLES.contains(&token.to_uppercase().as_str()) {
+                continue;
+            }
+            let re = Regex::new(&format!(r"(?i)\b{}\b", regex::escape(token))).unwrap();
+            out = re.replace_all(&out, "[NOME]").into_owned();
+        }
+    }
+    out
+}
+
+/// Consultations filed under a CPF merged into another record get the
+/// pseudonym and date shift of the CPF that was kept, so the same person is
+/// one patient in the export too.
+pub fn anonymized(conn: &Connection, files: &[(PathBuf, String)]) -> Vec<AnonymizedConsultation> {
+    let secret = pseudonym_secret(conn);
+    let aliases = patients::aliases(conn);
+    let mut out = Vec::new();
+    for (_, content) in files {
+        let Some(cons) = medfile::parse_med_file(content) else { continue };
+        let cpf = aliases.get(&cons.patient.cpf).unwrap_or(&cons.patient.cpf);
+        let shift = date_shift(&secret, cpf);
+        let names = [cons.patient.nome.as_str(), cons.doctor.nome.as_str()];
+        let age: i64 = medfile::calculate_age(&cons.patient.birth_date).parse().unwrap_or(0);
+        out.push(AnonymizedConsultation {
+            patient: pseudonym(&secret, "P", cpf),
+            age_group: age_group(age),
+            doctor: pseudonym(&secret, "D", &cons.doctor.crm),
+            especialidade: cons.doctor.especialidade.clone(),
+            date: shift_timestamp(&cons.timestamp, shift),
+            anamnese: scrub(&medfile::section_text(content, "ANAMNESE").unwrap_or_default(), &names),
+            hipotese_diagnostica: scrub(&cons.hipotese_diagnostica, &names),
+            medications: medfile::parse_conduta_commands(&cons.conduta)
+                .into_iter()
+                .filter(|(action, _, _)| matches!(action.as_str(), "PRESCREVO" | "AJUSTO" | "SUSPENDO" | "MANTENHO"))
+                .map(|(action, name, dosage)| MedicationEvent { action, name, dosage: scrub(&dosage, &names) })
+                .collect(),
+            exams: medfile::parse_exams(content)
+                .into_iter()
+                .map(|e| Exam { code: e.code, date: shift_partial_date(&e.date, shift), result: scrub(&e.result, &names) })
+                .collect(),
+        });
+    }
+    out.sort_by(|a, b| (&a.patient, &a.date).cmp(&(&b.patient, &b.date)));
+    out
+}
@@ -0,0 +1,210 @@
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::diagnostics::{self, Diagnostic};
+use crate::medfile::{self, Consultation};
+use crate::{access, patients, storage};
+
+/// A file received in a `multipart/form-data` body.
+pub struct Upload {
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Extracts the file parts of a `multipart/form-data` body.
+pub fn parse_multipart(content_type: &str, body: &[u8]) -> Result<Vec<Upload>, String> {
+    let boundary = content_type.split(';')
+        .find_map(|p| p.trim().strip_prefix("boundary="))
+        .map(|b| b.trim_matches('"'))
+        .ok_or("Envie os arquivos como multipart/form-data.")?;
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut uploads = Vec::new();
+    let mut rest = &body[find(body, &delimiter).ok_or("Corpo multipart sem delimitador.")? + delimiter.len()..];
+    while !rest.starts_with(b"--") {
+        let headers_end = find(rest, b"\r\n\r\n").ok_or("Parte multipart sem cabeçalhos.")?;
+        let headers = String::from_utf8_lossy(&rest[..headers_end]);
+        let data_start = headers_end + 4;
+        let part_end = find(&rest[data_start..], &delimiter).ok_or("Corpo multipart truncado.")? + data_start;
+        let data = rest[data_start..part_end].strip_suffix(b"\r\n").unwrap_or(&rest[data_start..part_end]);
+        let filename = headers.lines()
+            .filter(|l| l.to_ascii_lowercase().starts_with("content-disposition:"))
+            .flat_map(|l| l.split(';'))
+            .find_map(|p| p.trim().strip_prefix("filename=").map(|f| f.trim_matches('"').to_string()));
+        if let Some(filename) = filename.filter(|f| !f.is_empty()) {
+            uploads.push(Upload { filename, data: data.to_vec() });
+        }
+        rest = &rest[part_end + delimiter.len()..];
+    }
+    Ok(uploads)
+}
+
+#[derive(Serialize)]
+pub struct ImportPreview {
+    pub filename: String,
+    pub consultation: Option<Consultation>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Why the file cannot be imported, when it cannot.
+    pub rejected: Option<String>,
+    /// Where the file was stored, once the import is confirmed.
+    pub stored_as: Option<String>,
+}
+
+/// Parses and checks each upload against the existing records. The doctor
+/// `crm` may only import their own consultations, and only for new patients
+/// or patients whose record they may already read: authoring a consultation
+/// grants access to the whole record. `existing` is filed under merged CPFs
+/// already resolved, as `load_consultations` returns it; an upload under a CPF
+/// that was merged away is checked against the record it was merged into.
+pub fn preview(conn: &Connection, uploads: &[Upload], existing: &[Consultation], crm: &str) -> Vec<ImportPreview> {
+    let aliases = patients::aliases(conn);
+    let mut seen_tx: Vec<String> = existing.iter().map(|c| c.tx_id.clone()).collect();
+    uploads.iter().map(|upload| {
+        let mut item = ImportPreview {
+            filename: upload.filename.clone(),
+            consultation: None,
+            diagnostics: Vec::new(),
+            rejected: None,
+            stored_as: None,
+        };
+        if !upload.filename.ends_with(".med") {
+            item.rejected = Some("Apenas arquivos .med são aceitos.".to_string());
+            return item;
+        }
+        let Ok(content) = String::from_utf8(upload.data.clone()) else {
+            item.rejected = Some("Arquivo não está em UTF-8.".to_string());
+            return item;
+        };
+        item.diagnostics = diagnostics::check(&content);
+        item.consultation = medfile::parse_med_file(&content);
+        if diagnostics::has_errors(&item.diagnostics) {
+            item.rejected = Some("O arquivo tem erros.".to_string());
+        } else if let Some(cons) = &item.consultation && cons.doctor.crm != crm {
+            item.rejected = Some(format!("O CRM do [DOCTOR] ({}) não é o seu ({}).", cons.doctor.crm, crm));
+        } else if let Some(cons) = &item.consultation
+            && let cpf = aliases.get(&cons.patient.cpf).unwrap_or(&cons.patient.cpf)
+            && existing.iter().any(|c| c.patient.cpf == *cpf)
+            && !access::can_read(conn, cpf, crm, existing) {
+            item.rejected = Some(format!("Sem acesso ao prontuário de {}.", cpf));
+        } else if let Some(cons) = &item.consultation && !cons.tx_id.is_empty() {
+            if seen_tx.contains(&cons.tx_id) {
+                item.rejected = Some(format!("Transação {} já existe.", cons.tx_id));
+            } else {
+                seen_tx.push(cons.tx_id.clone());
+            }
+        }
+        item
+    }).collect()
+}
+
+/// Writes the accepted uploads into the medfiles directory, filling
+/// `stored_as`, and records them in `web_uploads` as sent by `username`;
+/// returns the consultations stored.
+pub fn store(conn: &Connection, uploads: &[Upload], previews: &mut [ImportPreview], username: &str) -> Result<Vec<Consultation>, String> {
+    let mut stored = Vec::new();
+    for (upload, item) in uploads.iter().zip(previews.iter_mut()) {
+        if item.rejected.is_some() {
+            continue;
+        }
+        let Some(cons) = item.consultation.clone() else { continue };
+        let path = medfile::available_path(&upload.filename);
+        storage::write_med(&path, &String::from_utf8_lossy(&upload.data)).map_err(|e| format!("{}: {}", path.display(), e))?;
+        item.stored_as = path.file_name().and_then(|n| n.to_str()).map(|n| n.to_string());
+        conn.execute("INSERT OR REPLACE INTO web_uploads (filename, username, uploaded_at) VALUES (?1, ?2, ?3)",
+                     [item.stored_as.as_deref().unwrap_or_default(), username, &chrono::Utc::now().to_rfc3339()]).unwrap();
+        stored.push(Consultation { filename: item.stored_as.clone().unwrap_or_default(), ..cons });
+    }
+    Ok(stored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CRM: &str = "10057-SP";
+    const KEPT: &str = "529.982.247-25";
+    const MERGED: &str = "111.444.777-35";
+
+    fn med(cpf: &str, crm: &str, tx_id: &str) -> String {
+        format!("[PATIENT]\nCPF: {}\nNome: Paciente Teste\nIdade: 40\n\n[DOCTOR]\nCRM: {}\nNome: Dra. Teste\nEspecialidade: Clinica Geral\n\n\
+                 [TRANSACTION]\nID: {}\nTimestamp: 2025-01-10T10:00:00Z\n\n[CONTRACT_CONDITIONS]\n- Paciente consente com o tratamento.\n\n\
+                 [CONTENT]\n\n[ANAMNESE]\nRETORNO.\n\n[HIPOTESE DIAGNOSTICA]\nHAS\n\n[CONDUTA]\n!ORIENTO DIETA;\n", cpf, crm, tx_id)
+    }
+
+    fn upload(cpf: &str, crm: &str, tx_id: &str) -> Upload {
+        Upload { filename: format!("{}.med", tx_id), data: med(cpf, crm, tx_id).into_bytes() }
+    }
+
+    /// A record under `KEPT`, written by another doctor, into which `MERGED` was merged.
+    fn records() -> (Connection, Vec<Consultation>) {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::create_tables(&conn);
+        conn.execute("INSERT INTO patient_aliases (cpf, merged_into) VALUES (?1, ?2)", [MERGED, KEPT]).unwrap();
+        let existing = vec![medfile::parse_med_file(&med(KEPT, "20000-RJ", "tx1")).unwrap()];
+        (conn, existing)
+    }
+
+    fn rejections(conn: &Connection, uploads: &[Upload], existing: &[Consultation]) -> Vec<Option<String>> {
+        preview(conn, uploads, existing, CRM).into_iter().map(|p| p.rejected).collect()
+    }
+
+    #[test]
+    fn multipart_files_are_extracted() {
+        let body = b"preamble\r\n--XyZ\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nnot a file\r\n\
+                     --XyZ\r\nContent-Disposition: form-data; name=\"files\"; filename=\"a.med\"\r\nContent-Type: text/plain\r\n\r\nlinha 1\r\nlinha 2\r\n\
+                     --XyZ\r\nContent-Disposition: form-data; name=\"files\"; filename=\"\"\r\n\r\n\r\n\
+                     --XyZ\r\nContent-Disposition: form-data; name=\"files\"; filename=\"b.med\"\r\n\r\n\r\n--XyZ--\r\n";
+        let uploads = parse_multipart("multipart/form-data; boundary=\"XyZ\"", body).unwrap();
+        let files: Vec<(&str, &[u8])> = uploads.iter().map(|u| (u.filename.as_str(), u.data.as_slice())).collect();
+        assert_eq!(files, [("a.med", b"linha 1\r\nlinha 2".as_slice()), ("b.med", b"".as_slice())]);
+    }
+
+    #[test]
+    fn multipart_errors() {
+        assert!(parse_multipart("application/json", b"{}").is_err());
+        assert!(parse_multipart("multipart/form-data; boundary=XyZ", b"sem delimitador").is_err());
+        assert!(parse_multipart("multipart/form-data; boundary=XyZ", b"--XyZ\r\nContent-Disposition: form-data\r\n\r\ntruncado").is_err());
+    }
+
+    #[test]
+    fn new_patients_and_own_consultations_are_accepted() {
+        let (conn, existing) = records();
+        assert_eq!(rejections(&conn, &[upload("123.456.789-09", CRM, "tx2")], &existing), [None]);
+    }
+
+    #[test]
+    fn other_doctors_consultations_are_rejected() {
+        let (conn, existing) = records();
+        assert_eq!(rejections(&conn, &[upload("123.456.789-09", "20000-RJ", "tx2")], &existing),
+                   [Some("O CRM do [DOCTOR] (20000-RJ) não é o seu (10057-SP).".to_string())]);
+    }
+
+    #[test]
+    fn existing_records_need_access() {
+        let (conn, existing) = records();
+        let denied = Some(format!("Sem acesso ao prontuário de {}.", KEPT));
+        assert_eq!(rejections(&conn, &[upload(KEPT, CRM, "tx2")], &existing), [denied]);
+        crate::access::grant(&conn, KEPT, CRM, None);
+        assert_eq!(rejections(&conn, &[upload(KEPT, CRM, "tx2")], &existing), [None]);
+    }
+
+    #[test]
+    fn merged_cpfs_are_checked_as_the_record_they_were_merged_into() {
+        let (conn, existing) = records();
+        assert_eq!(rejections(&conn, &[upload(MERGED, CRM, "tx2")], &existing),
+                   [Some(format!("Sem acesso ao prontuário de {}.", KEPT))]);
+    }
+
+    #[test]
+    fn repeated_transactions_are_rejected() {
+        let (conn, existing) = records();
+        let uploads = [upload("123.456.789-09", CRM, "tx2"), upload("123.456.789-09", CRM, "tx2"), upload(KEPT, CRM, "tx1")];
+        let rejected = rejections(&conn, &uploads[..2], &existing);
+        assert_eq!(rejected, [None, Some("Transação tx2 já existe.".to_string())]);
+        crate::access::grant(&conn, KEPT, CRM, None);
+        assert_eq!(rejections(&conn, &uploads[2..], &existing), [Some("Transação tx1 já existe.".to_string())]);
+    }
+}
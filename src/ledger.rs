@@ -0,0 +1,326 @@
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::cpf::Cpf;
+use crate::medfile;
+use crate::{signature, storage};
+
+/// `Previous:` value of the first transaction of a patient.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+/// `tx_id` of the row appended when the patient's data is erased: the rows
+/// before it have no files anymore, on purpose.
+pub const ERASURE: &str = "ERASURE";
+
+#[derive(Serialize, Clone)]
+pub struct LedgerEntry {
+    pub seq: i64,
+    pub cpf: String,
+    pub tx_id: String,
+    pub filename: String,
+    pub timestamp: String,
+    pub hash: String,
+    pub previous: String,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "issue", rename_all = "snake_case")]
+pub enum ChainIssue {
+    /// The ledger row does not point at the hash of the row before it.
+    BrokenLink { tx_id: String },
+    /// A sealed transaction has no file anymore. `filename` is empty unless
+    /// the row was sealed before the ledger stopped recording file names.
+    Deleted { tx_id: String, filename: String },
+    /// The file content no longer hashes to the sealed value.
+    Altered { tx_id: String, filename: String },
+    /// The files' chronological order disagrees with the ledger order.
+    Reordered { tx_id: String },
+    /// A consultation of the patient that was never sealed into the ledger.
+    Unsealed { tx_id: String, filename: String },
+    /// A file carrying a seal (`Previous:`, or `Hash:`/`Signature:` as
+    /// `mymed sign` writes them) whose transaction is not in the ledger.
+    Unrecorded { tx_id: String, filename: String },
+}
+
+impl ChainIssue {
+    /// Whether the issue means sealed history was tampered with, as opposed to
+    /// a consultation that simply has not been sealed yet.
+    pub fn is_tampering(&self) -> bool {
+        !matches!(self, ChainIssue::Unsealed { .. })
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            ChainIssue::BrokenLink { tx_id } => format!("{}: elo quebrado no ledger", tx_id),
+            ChainIssue::Deleted { tx_id, filename } if filename.is_empty() => format!("{}: arquivo removido", tx_id),
+            ChainIssue::Deleted { tx_id, filename } => format!("{}: arquivo {} removido", tx_id, filename),
+            ChainIssue::Altered { tx_id, filename } => format!("{}: conteúdo de {} alterado", tx_id, filename),
+            ChainIssue::Reordered { tx_id } => format!("{}: fora da ordem registrada", tx_id),
+            ChainIssue::Unsealed { tx_id, filename } => format!("{}: {} não registrado no ledger", tx_id, filename),
+            ChainIssue::Unrecorded { tx_id, filename } => format!("{}: {} selado, mas ausente do ledger", tx_id, filename),
+        }
+    }
+}
+
+/// The patient's ledger rows. The table is append-only, so rows sealed before
+/// CPFs were normalized keep the CPF as written and are matched here.
+pub fn entries(conn: &Connection, cpf: &str) -> Vec<LedgerEntry> {
+    let mut stmt = conn.prepare("SELECT seq, cpf, tx_id, filename, timestamp, hash, previous FROM ledger ORDER BY seq").unwrap();
+    stmt.query_map([], |row| {
+        Ok(LedgerEntry {
+            seq: row.get(0)?,
+            cpf: row.get(1)?,
+            tx_id: row.get(2)?,
+            filename: row.get(3)?,
+            timestamp: row.get(4)?,
+            hash: row.get(5)?,
+            previous: row.get(6)?,
+        })
+    }).unwrap().filter_map(|e| e.ok()).filter(|e| Cpf::normalize(&e.cpf) == cpf).collect()
+}
+
+pub fn last_hash(conn: &Connection, cpf: &str) -> String {
+    entries(conn, cpf).pop().map_or_else(|| GENESIS.to_string(), |e| e.hash)
+}
+
+/// Links the consultation to the patient's last transaction, signs it and
+/// appends it to the ledger. Returns the sealed content. The file name is not
+/// recorded: medfiles are named after the patient, and the ledger outlives
+/// the erasure of their data.
+pub fn seal(conn: &Connection, content: &str) -> Result<String, String> {
+    let cons = medfile::parse_med_file(content).ok_or("Falha ao parsear arquivo .med")?;
+    if cons.tx_id.is_empty() {
+        return Err("Arquivo sem ID de transação.".to_string());
+    }
+    if entries(conn, &cons.patient.cpf).iter().any(|e| e.tx_id == cons.tx_id) {
+        return Err(format!("Transação {} já registrada no ledger.", cons.tx_id));
+    }
+
+    let previous = last_hash(conn, &cons.patient.cpf);
+    let linked = medfile::set_transaction_field(content, "Previous", &previous);
+    let signed = signature::sign_content(&linked)?;
+    conn.execute("INSERT INTO ledger (cpf, tx_id, filename, timestamp, hash, previous, recorded_at) VALUES (?1, ?2, '', ?3, ?4, ?5, ?6)",
+                 [&cons.patient.cpf, &cons.tx_id, &cons.timestamp, &signature::content_hash(&signed), &previous,
+                  &chrono::Utc::now().to_rfc3339()]).map_err(|e| e.to_string())?;
+    Ok(signed)
+}
+
+/// Whether the file claims to have been sealed: only `seal` writes
+/// `Previous:`, and `Hash:`/`Signature:` in its `sha256:`/`ed25519:` form.
+/// Placeholder values, as in files from other systems, do not count.
+fn carries_seal(content: &str) -> bool {
+    let field = |name: &str, prefix: &str| medfile::transaction_field(content, name).is_some_and(|v| v.starts_with(prefix));
+    medfile::transaction_field(content, "Previous").is_some() || field("Hash", "sha256:") || field("Signature", "ed25519:")
+}
+
+/// Seals the consultation and writes it to `path` as one step: the ledger row
+/// is rolled back if the file cannot be written, so the ledger never records a
+/// transaction that is not on disk.
+pub fn seal_to_file(conn: &Connection, content: &str, path: &Path) -> Result<String, String> {
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let signed = seal(&tx, content)?;
+    storage::write_med(path, &signed).map_err(|e| format!("{}: {}", path.display(), e))?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(signed)
+}
+
+/// Closes the patient's chain after their .med files were erased (LGPD): the
+/// ledger is append-only, so the erasure is recorded as one more row linked
+/// to the last one instead of removing the sealed history. File names that
+/// older rows recorded are cleared, the one change the ledger allows. Returns
+/// false if the patient had nothing in the ledger since the last erasure.
+pub fn record_erasure(conn: &Connection, cpf: &str) -> Result<bool, String> {
+    conn.execute("UPDATE ledger SET filename = '' WHERE cpf = ?1 AND filename <> ''", [cpf]).map_err(|e| e.to_string())?;
+    let Some(last) = entries(conn, cpf).pop().filter(|e| e.tx_id != ERASURE) else { return Ok(false) };
+    let erased_at = chrono::Utc::now().to_rfc3339();
+    let hash = signature::content_hash(&format!("{}\n{}\n{}", ERASURE, erased_at, last.hash));
+    conn.execute("INSERT INTO ledger (cpf, tx_id, filename, timestamp, hash, previous, recorded_at) VALUES (?1, ?2, '', ?3, ?4, ?5, ?3)",
+                 [&last.cpf, ERASURE, &erased_at, &hash, &last.hash]).map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// Checks the patient's medfiles against the ledger.
+pub fn verify_chain(conn: &Connection, cpf: &str) -> Vec<ChainIssue> {
+    verify_files(conn, cpf, medfile::load_med_files())
+}
+
+fn verify_files(conn: &Connection, cpf: &str, med_files: Vec<(PathBuf, String)>) -> Vec<ChainIssue> {
+    let ledger = entries(conn, cpf);
+    let mut issues = Vec::new();
+
+    // tx_id -> (filename, timestamp, hash, looks sealed)
+    let mut files: HashMap<String, (String, String, String, bool)> = HashMap::new();
+    for (path, content) in med_files {
+        let Some(cons) = medfile::parse_med_file(&content) else { continue };
+        if cons.patient.cpf != cpf {
+            continue;
+        }
+        let filename = path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_string();
+        files.insert(cons.tx_id, (filename, cons.timestamp, signature::content_hash(&content), carries_seal(&content)));
+    }
+
+    let erased = ledger.iter().rposition(|e| e.tx_id == ERASURE);
+    let mut expected_previous = GENESIS.to_string();
+    for (i, entry) in ledger.iter().enumerate() {
+        if entry.previous != expected_previous {
+            issues.push(ChainIssue::BrokenLink { tx_id: entry.tx_id.clone() });
+        }
+        expected_previous = entry.hash.clone();
+        if erased.is_some_and(|e| i <= e) {
+            continue;
+        }
+        match files.get(&entry.tx_id) {
+            None => issues.push(ChainIssue::Deleted { tx_id: entry.tx_id.clone(), filename: entry.filename.clone() }),
+            Some((filename, _, hash, _)) if *hash != entry.hash => {
+                issues.push(ChainIssue::Altered { tx_id: entry.tx_id.clone(), filename: filename.clone() })
+            }
+            _ => {}
+        }
+    }
+
+    let position: HashMap<&str, usize> = ledger.iter().enumerate().map(|(i, e)| (e.tx_id.as_str(), i)).collect();
+    let mut sealed: Vec<(&String, &String)> = files.iter()
+        .filter(|(tx_id, _)| position.contains_key(tx_id.as_str()))
+        .map(|(tx_id, (_, timestamp, _, _))| (tx_id, timestamp))
+        .collect();
+    sealed.sort_by(|a, b| a.1.cmp(b.1).then(position[a.0.as_str()].cmp(&position[b.0.as_str()])));
+    let in_ledger_order: Vec<&String> = ledger.iter().map(|e| &e.tx_id).filter(|tx| files.contains_key(*tx)).collect();
+    for (found, expected) in sealed.iter().zip(in_ledger_order) {
+        if found.0 != expected {
+            issues.push(ChainIssue::Reordered { tx_id: found.0.clone() });
+        }
+    }
+
+    let mut unsealed: Vec<ChainIssue> = files.iter()
+        .filter(|(tx_id, _)| !position.contains_key(tx_id.as_str()))
+        .map(|(tx_id, (filename, _, _, sealed))| if *sealed {
+            ChainIssue::Unrecorded { tx_id: tx_id.clone(), filename: filename.clone() }
+        } else {
+            ChainIssue::Unsealed { tx_id: tx_id.clone(), filename: filename.clone() }
+        })
+        .collect();
+    unsealed.sort_by_key(|i| i.describe());
+    issues.extend(unsealed);
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CPF: &str = "529.982.247-25";
+
+    fn ledger_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE ledger (seq INTEGER PRIMARY KEY AUTOINCREMENT, cpf TEXT NOT NULL, tx_id TEXT NOT NULL,
+                      filename TEXT, timestamp TEXT, hash TEXT NOT NULL, previous TEXT NOT NULL, recorded_at TEXT)", []).unwrap();
+        conn
+    }
+
+    fn consultation(tx_id: &str, timestamp: &str) -> String {
+        format!("[PATIENT]\nCPF: {}\nNome: Paciente Teste\nIdade: 40\n\n[DOCTOR]\nCRM: 10057-SP\nNome: Dra. Teste\nEspecialidade: Clinica Geral\n\n\
+                 [TRANSACTION]\nID: {}\nTimestamp: {}\n\n[CONTRACT_CONDITIONS]\n- Paciente consente com o tratamento.\n\n[CONTENT]\n\n\
+                 [ANAMNESE]\nRETORNO.\n\n[HIPOTESE DIAGNOSTICA]\nHAS\n\n[CONDUTA]\n!ORIENTO DIETA;\n", CPF, tx_id, timestamp)
+    }
+
+    /// Seals like `seal` does, without a signing key: links the file to the
+    /// last row and appends it.
+    fn sealed(conn: &Connection, tx_id: &str, timestamp: &str) -> (PathBuf, String) {
+        let filename = format!("{}.med", tx_id);
+        let previous = last_hash(conn, CPF);
+        let content = medfile::set_transaction_field(&consultation(tx_id, timestamp), "Previous", &previous);
+        conn.execute("INSERT INTO ledger (cpf, tx_id, filename, timestamp, hash, previous) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                     [CPF, tx_id, &filename, timestamp, &signature::content_hash(&content), &previous]).unwrap();
+        (PathBuf::from(filename), content)
+    }
+
+    fn chain(conn: &Connection) -> Vec<(PathBuf, String)> {
+        vec![
+            sealed(conn, "tx1", "2025-01-10T10:00:00Z"),
+            sealed(conn, "tx2", "2025-02-10T10:00:00Z"),
+            sealed(conn, "tx3", "2025-03-10T10:00:00Z"),
+        ]
+    }
+
+    fn described(issues: &[ChainIssue]) -> Vec<String> {
+        issues.iter().map(|i| i.describe()).collect()
+    }
+
+    #[test]
+    fn intact_chain_has_no_issues() {
+        let conn = ledger_db();
+        let files = chain(&conn);
+        assert!(verify_files(&conn, CPF, files).is_empty());
+    }
+
+    #[test]
+    fn deleted_row_breaks_the_link_and_leaves_its_file_unrecorded() {
+        let conn = ledger_db();
+        let files = chain(&conn);
+        conn.execute("DELETE FROM ledger WHERE tx_id = 'tx2'", []).unwrap();
+        let issues = verify_files(&conn, CPF, files);
+        assert_eq!(described(&issues), ["tx3: elo quebrado no ledger", "tx2: tx2.med selado, mas ausente do ledger"]);
+        assert!(issues.iter().all(|i| i.is_tampering()));
+    }
+
+    #[test]
+    fn altered_file_is_reported() {
+        let conn = ledger_db();
+        let mut files = chain(&conn);
+        files[1].1 = files[1].1.replace("!ORIENTO DIETA;", "!PRESCREVO [LOSARTANA] [50MG];");
+        assert_eq!(described(&verify_files(&conn, CPF, files)), ["tx2: conteúdo de tx2.med alterado"]);
+    }
+
+    #[test]
+    fn removed_file_is_reported() {
+        let conn = ledger_db();
+        let mut files = chain(&conn);
+        files.remove(0);
+        assert_eq!(described(&verify_files(&conn, CPF, files)), ["tx1: arquivo tx1.med removido"]);
+    }
+
+    #[test]
+    fn reordered_files_are_reported() {
+        let conn = ledger_db();
+        let files = vec![
+            sealed(&conn, "tx1", "2025-03-10T10:00:00Z"),
+            sealed(&conn, "tx2", "2025-01-10T10:00:00Z"),
+        ];
+        let issues = verify_files(&conn, CPF, files);
+        assert_eq!(described(&issues), ["tx2: fora da ordem registrada", "tx1: fora da ordem registrada"]);
+        assert!(issues.iter().all(|i| i.is_tampering()));
+    }
+
+    #[test]
+    fn unsealed_file_is_not_tampering() {
+        let conn = ledger_db();
+        let mut files = chain(&conn);
+        files.push((PathBuf::from("tx4.med"), consultation("tx4", "2025-04-10T10:00:00Z")));
+        let issues = verify_files(&conn, CPF, files);
+        assert_eq!(described(&issues), ["tx4: tx4.med não registrado no ledger"]);
+        assert!(!issues[0].is_tampering());
+    }
+
+    #[test]
+    fn erasure_closes_the_chain() {
+        let conn = ledger_db();
+        chain(&conn);
+        assert!(record_erasure(&conn, CPF).unwrap());
+        assert!(!record_erasure(&conn, CPF).unwrap());
+        assert!(verify_files(&conn, CPF, Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn erasure_clears_recorded_filenames_and_nothing_else_changes() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::create_tables(&conn);
+        chain(&conn);
+        assert!(conn.execute("UPDATE ledger SET hash = 'x' WHERE tx_id = 'tx1'", []).is_err());
+        assert!(conn.execute("UPDATE ledger SET filename = 'outro.med' WHERE tx_id = 'tx1'", []).is_err());
+        assert!(conn.execute("DELETE FROM ledger WHERE tx_id = 'tx1'", []).is_err());
+        assert!(record_erasure(&conn, CPF).unwrap());
+        assert!(entries(&conn, CPF).iter().all(|e| e.filename.is_empty()));
+        assert_eq!(ChainIssue::Deleted { tx_id: "tx1".to_string(), filename: String::new() }.describe(), "tx1: arquivo removido");
+    }
+}
@@ -0,0 +1,147 @@
+use rusqlite::Connection;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::medfile::{self, Consultation, Exam, Patient};
+use crate::{access, audit, duplicates, ledger, medications, patients};
+
+/// Medical records must be kept for 20 years after the last entry
+/// (Lei 13.787/2018 and CFM Resolução 1.821/2007).
+pub const RETENTION_YEARS: i32 = 20;
+
+#[derive(Serialize)]
+pub struct MedicationEvent {
+    pub date: String,
+    pub tx_id: String,
+    pub action: String,
+    pub name: String,
+    pub dosage: String,
+}
+
+#[derive(Serialize)]
+pub struct CurrentMedication {
+    pub name: String,
+    pub dosage: String,
+    pub start_date: String,
+}
+
+#[derive(Serialize)]
+pub struct ExamRecord {
+    pub tx_id: String,
+    #[serde(flatten)]
+    pub exam: Exam,
+}
+
+#[derive(Serialize)]
+pub struct PatientArchive {
+    pub generated_at: String,
+    pub patient: Patient,
+    pub consultations: Vec<Consultation>,
+    pub medication_history: Vec<MedicationEvent>,
+    pub current_medications: Vec<CurrentMedication>,
+    pub exams: Vec<ExamRecord>,
+    pub access_grants: Vec<access::Grant>,
+    pub accesses: Vec<audit::AccessSummary>,
+}
+
+/// The patient's .med files, including those filed under CPFs merged into
+/// theirs, oldest consultation first.
+fn patient_files(cpf: &str) -> Vec<(PathBuf, String, Consultation)> {
+    let aliases = patients::aliases(&crate::db::open());
+    let mut files: Vec<_> = medfile::load_med_files().into_iter()
+        .filter_map(|(path, content)| medfile::parse_med_file(&content).map(|c| (path, content, c)))
+        .filter(|(_, _, c)| aliases.get(&c.patient.cpf).unwrap_or(&c.patient.cpf) == cpf)
+        .collect();
+    files.sort_by(|a, b| a.2.timestamp.cmp(&b.2.timestamp));
+    files
+}
+
+fn current_medications(conn: &Connection, cpf: &str) -> Result<Vec<CurrentMedication>, String> {
+    Ok(medications::stored(conn, cpf)?.into_iter()
+        .map(|(name, dosage, start_date)| CurrentMedication { name, dosage, start_date })
+        .collect())
+}
+
+/// Writes the portable copy of the patient's data into `out`: the .med files
+/// as plain text and `dados.json` with the structured record. Returns the
+/// number of consultations exported.
+pub fn export_patient(conn: &Connection, cpf: &str, out: &Path) -> Result<usize, String> {
+    let files = patient_files(cpf);
+    let Some((_, _, first)) = files.first() else {
+        return Err(format!("Nenhuma consulta encontrada para o CPF {}.", cpf));
+    };
+    let patient = first.patient.clone();
+    std::fs::create_dir_all(out.join("consultas")).map_err(|e| e.to_string())?;
+
+    let mut consultations: Vec<Consultation> = files.iter().map(|(_, _, c)| c.clone()).collect();
+    medfile::sort_chronologically(&mut consultations);
+    medfile::link_amendments(&mut consultations);
+    let mut medication_history = Vec::new();
+    let mut exams = Vec::new();
+    for (path, content, cons) in &files {
+        let name = path.file_name().ok_or("Nome de arquivo inválido.")?;
+        std::fs::write(out.join("consultas").join(name), content).map_err(|e| e.to_string())?;
+        for (action, name, dosage) in medfile::parse_conduta_commands(&cons.conduta) {
+            medication_history.push(MedicationEvent { date: cons.timestamp.clone(), tx_id: cons.tx_id.clone(), action, name, dosage });
+        }
+        exams.extend(medfile::parse_exams(content).into_iter().map(|exam| ExamRecord { tx_id: cons.tx_id.clone(), exam }));
+    }
+
+    let archive = PatientArchive {
+        generated_at: chrono::Utc::now().to_rfc3339(),
+        patient,
+        consultations,
+        medication_history,
+        current_medications: current_medications(conn, cpf)?,
+        exams,
+        access_grants: access::grants(conn, cpf),
+        accesses: audit::access_report(conn, cpf),
+    };
+    std::fs::write(out.join("dados.json"), serde_json::to_string_pretty(&archive).unwrap()).map_err(|e| e.to_string())?;
+    Ok(files.len())
+}
+
+/// Date until which the record must be kept, counted from the last consultation.
+pub fn retention_until(cpf: &str) -> Option<chrono::NaiveDate> {
+    let last = patient_files(cpf).into_iter().filter_map(|(_, _, c)| chrono::DateTime::parse_from_rfc3339(&c.timestamp).ok()).max()?;
+    last.date_naive().checked_add_months(chrono::Months::new(12 * RETENTION_YEARS as u32))
+}
+
+#[derive(Default)]
+pub struct Erasure {
+    pub files: usize,
+    pub rows: usize,
+    /// Ledger chains closed with an erasure row.
+    pub chains: usize,
+}
+
+/// Deletes the patient's .med files, their `web_uploads` rows and every row
+/// keyed by the CPF. The audit log is kept: it is the controller's record of
+/// who accessed the data. So is the ledger, which is append-only: its chains
+/// are closed with an erasure row instead, so `verify_chain` does not report
+/// the files as removed.
+pub fn erase_patient(conn: &Connection, cpf: &str, force: bool) -> Result<Erasure, String> {
+    if !force && let Some(until) = retention_until(cpf)
+        && until > chrono::Utc::now().date_naive() {
+        return Err(format!("Prontuário sob guarda obrigatória até {} ({} anos após o último registro). Use --force para apagar mesmo assim.",
+                           until.format("%d/%m/%Y"), RETENTION_YEARS));
+    }
+    let mut erasure = Erasure::default();
+    for (path, _, _) in patient_files(cpf) {
+        std::fs::remove_file(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        erasure.files += 1;
+        let filename = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        erasure.rows += conn.execute("DELETE FROM web_uploads WHERE filename = ?1", [filename]).unwrap();
+    }
+    let mut cpfs = patients::merged_from(conn, cpf);
+    cpfs.push(cpf.to_string());
+    for cpf in &cpfs {
+        for table in ["patient_medications", "patients", "access_grants"] {
+            erasure.rows += conn.execute(&format!("DELETE FROM {} WHERE cpf = ?1", table), [cpf]).unwrap();
+        }
+        erasure.chains += ledger::record_erasure(conn, cpf)? as usize;
+        duplicates::forget(conn, cpf);
+    }
+    conn.execute("DELETE FROM patient_aliases WHERE merged_into = ?1", [cpf]).unwrap();
+    Ok(erasure)
+}
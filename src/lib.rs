@@ -0,0 +1,22 @@
+pub mod access;
+pub mod audit;
+pub mod auth;
+pub mod cid10;
+pub mod cli;
+pub mod complete;
+pub mod consult;
+pub mod cpf;
+pub mod crm;
+pub mod db;
+pub mod diagnostics;
+pub mod doctors;
+pub mod duplicates;
+pub mod export;
+pub mod import;
+pub mod lgpd;
+pub mod ledger;
+pub mod medfile;
+pub mod medications;
+pub mod patients;
+pub mod signature;
+pub mod storage;
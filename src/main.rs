@@ -1,38 +1,18 @@
-use actix_web::{web, App, HttpServer, HttpResponse, Result};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::{web, App, FromRequest, HttpRequest, HttpServer, HttpResponse, Result};
 use actix_files as afs;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use walkdir::WalkDir;
-use regex::Regex;
-use rusqlite::Connection;
-use webbrowser;
 use clap::{Parser, Subcommand};
 use std::process::Command;
-use serde_json;
-
-#[derive(Serialize, Deserialize, Clone)]
-struct Patient {
-    cpf: String,
-    nome: String,
-    birth_date: String,
-}
-
-#[derive(Serialize, Deserialize, Clone)]
-struct Doctor {
-    crm: String,
-    nome: String,
-    especialidade: String,
-}
-
-#[derive(Serialize, Deserialize, Clone)]
-struct Consultation {
-    patient: Patient,
-    doctor: Doctor,
-    timestamp: String,
-    filename: String,
-    hipotese_diagnostica: String,
-    conduta: String,
-}
+use mymed::db::{self, setup_db};
+use mymed::medfile::{self, calculate_age, find_med_file, link_amendments, load_consultations, parse_med_file,
+                     parse_patient_from_med, Consultation, Doctor, Patient};
+use mymed::auth::Permission;
+use mymed::cli::{self, confirm, fail, interactive, prompt, report};
+use mymed::cpf::Cpf;
+use mymed::crm::Crm;
+use mymed::{access, audit, auth, complete, consult, diagnostics, doctors, export, import, ledger, lgpd, medications, patients, signature, storage};
 
 #[derive(Serialize)]
 struct Medication {
@@ -47,6 +27,19 @@ struct PatientSummary {
     consultations: Vec<Consultation>,
     current_medications: Vec<Medication>,
     age: String,
+    /// False when the clinical sections were withheld for the user's role.
+    clinical: bool,
+}
+
+impl PatientSummary {
+    fn without_clinical_data(self) -> PatientSummary {
+        PatientSummary {
+            consultations: self.consultations.into_iter().map(Consultation::without_clinical_data).collect(),
+            current_medications: Vec::new(),
+            clinical: false,
+            ..self
+        }
+    }
 }
 
 #[derive(Parser)]
@@ -56,6 +49,9 @@ struct PatientSummary {
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+    /// Saída em JSON, para scripts
+    #[arg(long, global = true)]
+    json: bool,
 }
 
 #[derive(Subcommand)]
@@ -79,18 +75,129 @@ enum Commands {
     Web,
     #[command(name = "upgrade")]
     Upgrade,
+    /// Gerencia as chaves Ed25519 dos médicos
+    #[command(name = "keys")]
+    Keys {
+        #[command(subcommand)]
+        sub: KeysSub,
+    },
+    /// Encadeia ao ledger do paciente e assina com a chave do médico do bloco [DOCTOR]
+    #[command(name = "sign")]
+    Sign {
+        path: String,
+    },
+    /// Gerencia os usuários do servidor web
+    #[command(name = "user")]
+    User {
+        #[command(subcommand)]
+        sub: UserSub,
+    },
+    /// Concede a um médico acesso ao prontuário do paciente
+    #[command(name = "grant")]
+    Grant {
+        #[arg(long)]
+        cpf: Cpf,
+        #[arg(long)]
+        crm: Crm,
+        /// Validade do acesso em dias (sem prazo se omitido)
+        #[arg(long)]
+        days: Option<i64>,
+    },
+    /// Revoga o acesso de um médico ao prontuário do paciente
+    #[command(name = "revoke")]
+    Revoke {
+        #[arg(long)]
+        cpf: Cpf,
+        #[arg(long)]
+        crm: Crm,
+    },
+    /// Lista os acessos concedidos pelo paciente
+    #[command(name = "grants")]
+    Grants {
+        #[arg(long)]
+        cpf: Cpf,
+    },
+    /// Direitos do titular (LGPD): portabilidade e eliminação dos dados
+    #[command(name = "patient")]
+    Patient {
+        #[command(subcommand)]
+        sub: PatientSub,
+    },
+    /// Exporta dados para compartilhamento
+    #[command(name = "export")]
+    Export {
+        #[command(subcommand)]
+        sub: ExportSub,
+    },
+    /// Migra os .med e as colunas sensíveis do DB para armazenamento criptografado
+    #[command(name = "encrypt")]
+    Encrypt,
+    /// Desfaz a criptografia dos .med e do DB
+    #[command(name = "decrypt")]
+    Decrypt,
+    /// Consulta o registro de auditoria de acessos
+    #[command(name = "audit")]
+    Audit {
+        #[arg(long)]
+        cpf: Option<Cpf>,
+        #[arg(long)]
+        user: Option<String>,
+        /// Relatório "quem acessou meus dados" do paciente (requer --cpf)
+        #[arg(long)]
+        report: bool,
+    },
+    /// Cria um adendo para corrigir uma consulta sem alterar o original
+    #[command(name = "amend")]
+    Amend {
+        #[arg(long)]
+        tx: String,
+    },
+    /// Verifica a estrutura, os campos e as diretivas de arquivos .med
+    #[command(name = "check")]
+    Check {
+        /// Arquivos a verificar (padrão: todos do diretório de medfiles)
+        paths: Vec<String>,
+    },
+    /// Escreve consultas no editor do terminal ($VISUAL/$EDITOR)
+    #[command(name = "consult")]
+    Consult {
+        #[command(subcommand)]
+        sub: ConsultSub,
+    },
+    /// Confere a cadeia de transações de um paciente contra o ledger
+    #[command(name = "verify-chain")]
+    VerifyChain {
+        #[arg(long)]
+        cpf: Cpf,
+    },
 }
 
 #[derive(Subcommand)]
 enum NewSub {
+    /// Cadastra um paciente; pergunta no terminal o que não vier por opção
     #[command(name = "patient")]
-    Patient,
+    Patient {
+        #[arg(long)]
+        cpf: Option<String>,
+        #[arg(long)]
+        nome: Option<String>,
+        /// Data de nascimento (DD/MM/AAAA ou AAAA-MM-DD)
+        #[arg(long)]
+        nascimento: Option<String>,
+    },
 }
 
 #[derive(Subcommand)]
 enum ImportSub {
+    /// Cadastra o paciente do bloco [PATIENT] de um .med
     #[command(name = "patient")]
-    Patient,
+    Patient {
+        #[arg(long)]
+        file: Option<String>,
+        /// Confirma sem perguntar
+        #[arg(long)]
+        yes: bool,
+    },
 }
 
 #[derive(Subcommand)]
@@ -99,218 +206,324 @@ enum ListSub {
     Patients,
 }
 
-fn parse_med_file(content: &str) -> Option<Consultation> {
-    let patient_re = Regex::new(r"(?s)\[PATIENT\]\s*(.*?)\[DOCTOR\]").unwrap();
-    let doctor_re = Regex::new(r"(?s)\[DOCTOR\]\s*(.*?)\[TRANSACTION\]").unwrap();
-    let transaction_re = Regex::new(r"(?s)\[TRANSACTION\]\s*(.*?)\[CONTRACT_CONDITIONS\]").unwrap();
-    let diagnostica_re = Regex::new(r"(?s)\[HIPOTESE DIAGNOSTICA\]\s*(.*?)(?:\n\n|\[)").unwrap();
-    let conduta_re = Regex::new(r"(?s)\[CONDUTA\]\s*(.*?)(?:\n\n|\[|$)").unwrap();
-
-    let patient_section = patient_re.captures(content)?.get(1)?.as_str();
-    let doctor_section = doctor_re.captures(content)?.get(1)?.as_str();
-    let transaction_section = transaction_re.captures(content)?.get(1)?.as_str();
-    let diagnostica = diagnostica_re.captures(content).map_or("", |c| c.get(1).map_or("", |m| m.as_str().trim()));
-    let conduta = conduta_re.captures(content).map_or("", |c| c.get(1).map_or("", |m| m.as_str().trim()));
-
-    let cpf = extract_field(patient_section, "CPF:")?;
-    let nome = extract_field(patient_section, "Nome:")?;
-    let idade_str = extract_field(patient_section, "Idade:")?;
-    let idade_num: i32 = idade_str.parse().unwrap_or(0);
-    let today = chrono::Utc::now().date_naive();
-    let birth = today - chrono::Duration::days(idade_num as i64 * 365);
-    let birth_date = birth.format("%Y-%m-%d").to_string();
-
-    let crm = extract_field(doctor_section, "CRM:")?;
-    let doc_nome = extract_field(doctor_section, "Nome:")?;
-    let especialidade = extract_field(doctor_section, "Especialidade:")?;
-
-    let timestamp = extract_field(transaction_section, "Timestamp:")?;
-
-    Some(Consultation {
-        patient: Patient { cpf, nome, birth_date },
-        doctor: Doctor { crm, nome: doc_nome, especialidade },
-        timestamp,
-        filename: "".to_string(), // will set later
-        hipotese_diagnostica: diagnostica.to_string(),
-        conduta: conduta.to_string(),
-    })
+#[derive(Subcommand)]
+enum UserSub {
+    #[command(name = "add")]
+    Add {
+        #[arg(long)]
+        username: String,
+        #[arg(long)]
+        nome: String,
+        /// doctor, receptionist ou admin
+        #[arg(long)]
+        role: String,
+        #[arg(long)]
+        crm: Option<Crm>,
+    },
+    #[command(name = "list")]
+    List,
 }
 
-fn extract_field(section: &str, key: &str) -> Option<String> {
-    for line in section.lines() {
-        if line.starts_with(key) {
-            return Some(line[key.len()..].trim().to_string());
-        }
-    }
-    None
+#[derive(Subcommand)]
+enum PatientSub {
+    /// Gera uma cópia portátil dos dados do paciente (.med e dados.json)
+    #[command(name = "export")]
+    Export {
+        #[arg(long)]
+        cpf: Cpf,
+        /// Diretório de saída (padrão: paciente_<cpf>)
+        #[arg(long)]
+        out: Option<String>,
+    },
+    /// Apaga os .med e os registros do paciente
+    #[command(name = "erase")]
+    Erase {
+        #[arg(long)]
+        cpf: Cpf,
+        /// Apaga mesmo dentro do prazo de guarda do prontuário
+        #[arg(long)]
+        force: bool,
+    },
 }
 
-fn calculate_age(birth_date: &str) -> String {
-    let birth = chrono::NaiveDate::parse_from_str(birth_date, "%Y-%m-%d").unwrap_or(chrono::Utc::now().date_naive());
-    let today = chrono::Utc::now().date_naive();
-    let age = (today - birth).num_days() / 365;
-    age.to_string()
+#[derive(Subcommand)]
+enum ConsultSub {
+    /// Abre um modelo preenchido, valida ao sair e salva/sela se confirmado
+    #[command(name = "new")]
+    New {
+        #[arg(long)]
+        cpf: Cpf,
+        /// CRM do médico (padrão: MYMED_CRM)
+        #[arg(long)]
+        crm: Option<Crm>,
+    },
 }
 
-fn parse_patient_from_med(content: &str) -> Option<Patient> {
-    let patient_re = Regex::new(r"(?s)\[PATIENT\]\s*(.*?)\[DOCTOR\]").unwrap();
-    let patient_section = patient_re.captures(content)?.get(1)?.as_str();
-
-    let cpf = extract_field(patient_section, "CPF:")?;
-    let nome = extract_field(patient_section, "Nome:")?;
-    let idade_str = extract_field(patient_section, "Idade:")?;
-    let idade_num: i32 = idade_str.parse().ok()?;
-    let today = chrono::Utc::now().date_naive();
-    let birth = today - chrono::Duration::days(idade_num as i64 * 365);
-    let birth_date = birth.format("%Y-%m-%d").to_string();
+#[derive(Subcommand)]
+enum ExportSub {
+    /// Consultas pseudonimizadas para pesquisa, uma por linha (JSON Lines)
+    #[command(name = "anonymized")]
+    Anonymized {
+        #[arg(long)]
+        out: Option<String>,
+    },
+}
 
-    Some(Patient { cpf, nome, birth_date })
+#[derive(Subcommand)]
+enum KeysSub {
+    #[command(name = "generate")]
+    Generate {
+        #[arg(long)]
+        crm: Crm,
+    },
 }
 
-fn load_consultations() -> Vec<Consultation> {
-    let mut consultations = Vec::new();
-    for entry in WalkDir::new("/home/woulschneider/petridish/mymed/medfiles").into_iter().filter_map(|e| e.ok()) {
-        if entry.path().extension().and_then(|s| s.to_str()) == Some("med") {
-            if let Ok(content) = std::fs::read_to_string(entry.path()) {
-                if let Some(mut cons) = parse_med_file(&content) {
-                    if let Some(file_name) = entry.path().file_name().and_then(|n| n.to_str()) {
-                        cons.filename = file_name.to_string();
-                        consultations.push(cons);
-                    }
-                }
+fn group_by_patient(consultations: Vec<Consultation>) -> HashMap<String, PatientSummary> {
+    let mut patients_map: HashMap<String, PatientSummary> = HashMap::new();
+
+    for cons in consultations {
+        let key = cons.patient.cpf.clone();
+        patients_map.entry(key).or_insert_with(|| {
+            let age = calculate_age(&cons.patient.birth_date);
+            PatientSummary {
+                patient: cons.patient.clone(),
+                consultations: Vec::new(),
+                current_medications: Vec::new(),
+                age,
+                clinical: true,
             }
-        }
+        }).consultations.push(cons);
     }
-    consultations
-}
-
-fn setup_db() {
-    let conn = Connection::open("medications.db").unwrap();
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS patients (
-            cpf TEXT PRIMARY KEY,
-            nome TEXT,
-            birth_date TEXT
-        )",
-        [],
-    ).unwrap();
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS medications (
-            id INTEGER PRIMARY KEY,
-            name TEXT UNIQUE
-        )",
-        [],
-    ).unwrap();
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS patient_medications (
-            cpf TEXT,
-            med_id INTEGER,
-            status TEXT,
-            dosage TEXT,
-            start_date TEXT,
-            end_date TEXT,
-            FOREIGN KEY(cpf) REFERENCES patients(cpf),
-            FOREIGN KEY(med_id) REFERENCES medications(id)
-        )",
-        [],
-    ).unwrap();
-}
-
-fn process_medications(consultations: &[Consultation]) {
-    let conn = Connection::open("medications.db").unwrap();
-    let mut patients_map: HashMap<String, (Patient, Vec<Consultation>)> = HashMap::new();
+    for summary in patients_map.values_mut() {
+        link_amendments(&mut summary.consultations);
+    }
+    patients_map
+}
 
-    for cons in consultations {
-        patients_map.entry(cons.patient.cpf.clone()).or_insert_with(|| (cons.patient.clone(), Vec::new())).1.push(cons.clone());
+/// The logged-in user, resolved from the session cookie.
+struct AuthUser(auth::User);
+
+impl FromRequest for AuthUser {
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<std::result::Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let user = req.cookie(auth::SESSION_COOKIE).and_then(|c| auth::session_user(&db::open(), c.value()));
+        std::future::ready(user.map(AuthUser).ok_or_else(|| {
+            actix_web::error::InternalError::from_response("unauthenticated", unauthorized()).into()
+        }))
     }
+}
 
-    for (cpf, (patient, mut cons)) in patients_map {
-        cons.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
-        let mut current_meds: HashMap<String, String> = HashMap::new(); // med -> dosage
+/// Summary of a patient registered in `patients` who has no consultation yet.
+fn registered_summary(patient: Patient) -> PatientSummary {
+    PatientSummary {
+        age: calculate_age(&patient.birth_date),
+        patient,
+        consultations: Vec::new(),
+        current_medications: Vec::new(),
+        clinical: false,
+    }
+}
 
-            conn.execute("INSERT OR REPLACE INTO patients (cpf, nome, birth_date) VALUES (?1, ?2, ?3)",
-             [&cpf, &patient.nome, &patient.birth_date]).unwrap();
+/// The registration in `patients` is the reference for name and birth date,
+/// so corrections made through the API show over what the medfiles say.
+fn apply_registration(conn: &rusqlite::Connection, summary: &mut PatientSummary) {
+    if let Some(registered) = patients::get(conn, &summary.patient.cpf) {
+        summary.age = calculate_age(&registered.birth_date);
+        summary.patient = registered;
+    }
+}
 
-        for c in &cons {
-            let commands = parse_conduta_commands(&c.conduta);
-            for (action, med, dosage) in commands {
-                match action.as_str() {
-                    "PRESCREVO" => {
-                        current_meds.insert(med.clone(), dosage.clone());
-                    }
-                    "AJUSTO" => {
-                        current_meds.insert(med.clone(), dosage.clone());
-                    }
-                    "SUSPENDO" => {
-                        current_meds.remove(&med);
-                    }
-                    "MANTENHO" => {
-                        // Keep if already present, or add if not (assuming previous prescription)
-                        if !current_meds.contains_key(&med) {
-                            current_meds.insert(med.clone(), dosage.clone());
-                        }
-                    }
-                    _ => {}
-                }
-            }
-        }
+/// Records in the audit log that the user touched the given patients.
+fn audit_request<'a>(req: &HttpRequest, user: &auth::User, action: &str, cpfs: impl IntoIterator<Item = &'a str>) {
+    let conn = db::open();
+    let ip = req.peer_addr().map(|addr| addr.ip().to_string());
+    for cpf in cpfs {
+        audit::record(&conn, &user.username, action, Some(cpf), req.path(), ip.as_deref());
+    }
+}
 
-        // Clear old
-        conn.execute("DELETE FROM patient_medications WHERE cpf = ?1", [&cpf]).unwrap();
+/// The value of `--name`, asked for when missing and a terminal is attached.
+fn flag_or_prompt(json: bool, name: &str, value: Option<String>, question: &str) -> String {
+    match value {
+        Some(value) => value,
+        None if interactive() => prompt(question),
+        None => fail(json, format!("Informe --{} (sem terminal para perguntar).", name)),
+    }
+}
 
-        // Insert current
-        for (med, dosage) in current_meds {
-            conn.execute("INSERT OR IGNORE INTO medications (name) VALUES (?1)", [&med]).unwrap();
-            let med_id = conn.query_row("SELECT id FROM medications WHERE name = ?1", [&med], |row| row.get::<_, i64>(0)).unwrap();
-            conn.execute("INSERT INTO patient_medications (cpf, med_id, status, dosage, start_date) VALUES (?1, ?2, 'active', ?3, ?4)",
-                (&cpf, &med_id, &dosage, &cons.first().unwrap().timestamp)).unwrap();
-        }
+/// The text being edited by `mymed consult new`, in a directory only the user
+/// can read. Both are removed when the draft goes out of scope.
+struct Draft {
+    dir: std::path::PathBuf,
+    path: std::path::PathBuf,
+}
+
+impl Draft {
+    fn create(tx_id: &str, content: &str) -> std::io::Result<Draft> {
+        let dir = std::env::temp_dir().join(format!("mymed_{}", tx_id));
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(&dir)?;
+        let draft = Draft { path: dir.join("consulta.med"), dir };
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        std::io::Write::write_all(&mut options.open(&draft.path)?, content.as_bytes())?;
+        Ok(draft)
     }
 }
 
-fn parse_conduta_commands(conduta: &str) -> Vec<(String, String, String)> {
-    let mut commands = Vec::new();
-    // Split by ; to get individual commands
-    for part in conduta.split(';') {
-        let part = part.trim();
-        if part.starts_with('!') {
-            let re_bracket = Regex::new(r"!(\w+)\s*\[([^\]]+)\](?:\s*\[([^\]]+)\])?").unwrap();
-            let re_plain = Regex::new(r"!(\w+)\s*([^;\s]+)(?:\s*(.+))?").unwrap();
-            let (action, med, dosage) = if let Some(cap) = re_bracket.captures(part) {
-                (cap.get(1).unwrap().as_str().to_uppercase(),
-                 cap.get(2).unwrap().as_str().to_string(),
-                 cap.get(3).map_or("", |m| m.as_str()).to_string())
-            } else if let Some(cap) = re_plain.captures(part) {
-                (cap.get(1).unwrap().as_str().to_uppercase(),
-                 cap.get(2).unwrap().as_str().to_string(),
-                 cap.get(3).map_or("", |m| m.as_str()).to_string())
-            } else {
-                continue;
+impl Drop for Draft {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// `mymed consult new`: edits a header-filled template in `$VISUAL`/`$EDITOR`
+/// and, after each session, shows the diagnostics and the medication changes
+/// and asks whether to save, edit again or discard.
+fn consult_new(cpf: &str, crm: &str, json: bool) -> std::io::Result<()> {
+    if !interactive() {
+        fail(json, "mymed consult new precisa de um terminal para o editor e a revisão.");
+    }
+    setup_db();
+    let conn = db::open();
+    let Some(chart) = consult::chart(&conn, cpf) else {
+        fail(json, "Paciente não encontrado.");
+    };
+    let Some(doctor) = consult::doctor(&conn, crm) else {
+        fail(json, format!("Médico com CRM {} não encontrado.", crm));
+    };
+    let tx_id = medfile::new_tx_id();
+    let template = medfile::consultation_template(&chart.patient, &doctor, &tx_id, &[]);
+    let draft = Draft::create(&tx_id, &template)?;
+    let path = &draft.path;
+    let editor = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string());
+    // The review after each editing session is for people; with --json it
+    // goes to stderr and stdout only gets the outcome.
+    let note = |line: String| if json { eprintln!("{}", line) } else { println!("{}", line) };
+
+    loop {
+        let status = Command::new("sh").arg("-c").arg(format!("{} \"$1\"", editor)).arg("sh").arg(path).status()?;
+        if !status.success() {
+            note(format!("O editor terminou com erro ({}).", status));
+        }
+        let content = std::fs::read_to_string(path)?;
+        if content == template {
+            report(json, serde_json::json!({ "saved": false }), "Consulta não alterada; nada foi salvo.");
+            break;
+        }
+        let found = diagnostics::check(&content);
+        for d in &found {
+            note(d.describe());
+        }
+        let errors = diagnostics::has_errors(&found);
+        let problem = if errors { None } else { consult::check_new(&content, cpf).err() };
+        if let Some(problem) = &problem {
+            note(problem.clone());
+        }
+        if let Some(cons) = parse_med_file(&content) {
+            let mut meds = chart.medications.clone();
+            let changes = medications::apply_conduta(&mut meds, &cons.conduta);
+            if !changes.is_empty() {
+                note("Medicações:".to_string());
+                for change in &changes {
+                    note(format!("  {}", change.describe()));
+                }
+            }
+        }
+        let valid = !errors && problem.is_none();
+        let question = match (valid, signature::has_signing_key(crm)) {
+            (false, _) => "Corrigir? [e]ditar de novo, [d]escartar",
+            (true, true) => "[s]alvar e selar, [e]ditar de novo, [d]escartar",
+            (true, false) => "[s]alvar, [e]ditar de novo, [d]escartar",
+        };
+        let answer = loop {
+            let Some(answer) = cli::ask(question) else {
+                break 'd';
             };
-            commands.push((action, med, dosage));
+            match answer.to_lowercase().as_str() {
+                "s" if valid => break 's',
+                "e" | "" => break 'e',
+                "d" => break 'd',
+                _ => {}
+            }
+        };
+        match answer {
+            's' => match consult::save(&conn, &content, &audit::cli_user(), "consult new", None) {
+                Ok((filename, signed)) => {
+                    report(json, serde_json::json!({ "saved": true, "filename": filename, "signed": signed }),
+                           format!("Consulta salva em {}{}.", filename, if signed { ", assinada e selada no ledger" } else { "" }));
+                    break;
+                }
+                // Back to the question, so the text is not lost and the
+                // doctor can try again or discard it.
+                Err(e) => note(format!("Falha ao salvar: {}.", e)),
+            },
+            'd' => {
+                report(json, serde_json::json!({ "saved": false }), "Consulta descartada.");
+                break;
+            }
+            _ => {}
         }
     }
-    commands
+    Ok(())
 }
 
-async fn get_patients(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
-    let consultations = load_consultations();
-    let mut patients_map: HashMap<String, PatientSummary> = HashMap::new();
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized().json(serde_json::json!({ "error": "Autenticação necessária" }))
+}
 
-    for cons in consultations {
-        let key = cons.patient.cpf.clone();
-        patients_map.entry(key).or_insert_with(|| {
-            let age = calculate_age(&cons.patient.birth_date);
-            PatientSummary {
-                patient: cons.patient.clone(),
-                consultations: Vec::new(),
-                current_medications: Vec::new(),
-                age,
-            }
-        }).consultations.push(cons);
+fn forbidden() -> HttpResponse {
+    HttpResponse::Forbidden().json(serde_json::json!({ "error": "Acesso não autorizado pelo paciente" }))
+}
+
+fn not_permitted() -> HttpResponse {
+    HttpResponse::Forbidden().json(serde_json::json!({ "error": "Permissão insuficiente para este perfil" }))
+}
+
+/// Clinical data is shown to doctors who wrote part of the record or hold an
+/// active grant from the patient.
+fn can_read_clinical(conn: &rusqlite::Connection, user: &auth::User, summary: &PatientSummary) -> bool {
+    user.role.can(Permission::ReadClinical)
+        && user.crm.as_ref().is_some_and(|crm| access::can_read(conn, &summary.patient.cpf, crm, &summary.consultations))
+}
+
+/// Patients visible to the user: doctors see the records they may read,
+/// other roles see every patient without the clinical sections.
+fn visible_patients(user: &auth::User) -> Vec<PatientSummary> {
+    if !user.role.can(Permission::ReadDemographics) {
+        return Vec::new();
     }
+    let conn = db::open();
+    let grouped = group_by_patient(load_consultations());
+    let registered: Vec<PatientSummary> = patients::all(&conn).into_iter()
+        .filter(|p| !grouped.contains_key(&p.cpf))
+        .map(registered_summary)
+        .collect();
+    grouped
+        .into_values()
+        .filter_map(|p| {
+            if user.role.can(Permission::ReadClinical) {
+                can_read_clinical(&conn, user, &p).then_some(p)
+            } else {
+                Some(p.without_clinical_data())
+            }
+        })
+        .map(|mut p| {
+            apply_registration(&conn, &mut p);
+            p
+        })
+        .chain(registered)
+        .collect()
+}
 
-    let mut patients: Vec<PatientSummary> = patients_map.into_iter().map(|(_, v)| v).collect();
+async fn get_patients(req: HttpRequest, user: AuthUser, query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
+    let mut patients = visible_patients(&user.0);
     patients.sort_by(|a, b| a.patient.nome.cmp(&b.patient.nome));
     eprintln!("Grouped into {} patients", patients.len());
 
@@ -320,28 +533,13 @@ async fn get_patients(query: web::Query<HashMap<String, String>>) -> Result<Http
     let _end = start + limit;
     let paginated = patients.into_iter().skip(start).take(limit).collect::<Vec<_>>();
     eprintln!("Returning {} patients for page {}", paginated.len(), page);
+    audit_request(&req, &user.0, "list", paginated.iter().map(|p| p.patient.cpf.as_str()));
 
     Ok(HttpResponse::Ok().json(paginated))
 }
 
-async fn search_patients(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
-    let consultations = load_consultations();
-    let mut patients_map: HashMap<String, PatientSummary> = HashMap::new();
-
-    for cons in consultations {
-        let key = cons.patient.cpf.clone();
-        patients_map.entry(key).or_insert_with(|| {
-            let age = calculate_age(&cons.patient.birth_date);
-            PatientSummary {
-                patient: cons.patient.clone(),
-                consultations: Vec::new(),
-                current_medications: Vec::new(),
-                age,
-            }
-        }).consultations.push(cons);
-    }
-
-    let patients: Vec<PatientSummary> = patients_map.into_iter().map(|(_, v)| v).collect();
+async fn search_patients(req: HttpRequest, user: AuthUser, query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
+    let patients = visible_patients(&user.0);
 
     let filtered: Vec<PatientSummary> = if let Some(q) = query.get("q") {
         patients.into_iter().filter(|p|
@@ -351,58 +549,455 @@ async fn search_patients(query: web::Query<HashMap<String, String>>) -> Result<H
     } else {
         patients
     };
+    audit_request(&req, &user.0, "search", filtered.iter().map(|p| p.patient.cpf.as_str()));
 
     Ok(HttpResponse::Ok().json(filtered))
 }
 
-async fn get_patient(path: web::Path<String>) -> Result<HttpResponse> {
-    let cpf = path.into_inner();
-    let consultations = load_consultations();
-    let mut patients_map: HashMap<String, PatientSummary> = HashMap::new();
-
-    for cons in consultations {
-        let key = cons.patient.cpf.clone();
-        patients_map.entry(key).or_insert_with(|| {
-            let age = calculate_age(&cons.patient.birth_date);
-            PatientSummary {
-                patient: cons.patient.clone(),
-                consultations: Vec::new(),
-                current_medications: Vec::new(),
-                age,
-            }
-        }).consultations.push(cons);
-    }
+async fn get_patient(req: HttpRequest, user: AuthUser, path: web::Path<Cpf>) -> Result<HttpResponse> {
+    let cpf = String::from(path.into_inner());
+    let mut patients_map = group_by_patient(load_consultations());
 
     if let Some(mut patient) = patients_map.remove(&cpf) {
-        let conn = Connection::open("medications.db").unwrap();
-        let mut stmt = conn.prepare("SELECT m.name, pm.dosage, pm.start_date FROM patient_medications pm JOIN medications m ON pm.med_id = m.id WHERE pm.cpf = ?1 AND pm.status = 'active'").unwrap();
-        let meds_iter = stmt.query_map([&cpf], |row| {
-            Ok(Medication {
-                name: row.get(0)?,
-                dosage: row.get(1)?,
-                start_date: row.get(2)?,
-            })
-        }).unwrap();
-        for med in meds_iter {
-            patient.current_medications.push(med.unwrap());
+        let conn = db::open();
+        apply_registration(&conn, &mut patient);
+        if !user.0.role.can(Permission::ReadClinical) {
+            if !user.0.role.can(Permission::ReadDemographics) {
+                return Ok(not_permitted());
+            }
+            audit_request(&req, &user.0, "read_demographics", [cpf.as_str()]);
+            return Ok(HttpResponse::Ok().json(patient.without_clinical_data()));
+        }
+        if !can_read_clinical(&conn, &user.0, &patient) {
+            return Ok(forbidden());
         }
+        let meds = match medications::stored(&conn, &cpf) {
+            Ok(meds) => meds,
+            Err(e) => return Ok(HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))),
+        };
+        for (name, dosage, start_date) in meds {
+            patient.current_medications.push(Medication { name, dosage, start_date });
+        }
+        audit_request(&req, &user.0, "read", [cpf.as_str()]);
         Ok(HttpResponse::Ok().json(patient))
+    } else if let Some(registered) = patients::get(&db::open(), &cpf) {
+        if !user.0.role.can(Permission::ReadDemographics) {
+            return Ok(not_permitted());
+        }
+        audit_request(&req, &user.0, "read_demographics", [cpf.as_str()]);
+        Ok(HttpResponse::Ok().json(registered_summary(registered)))
     } else {
         Ok(HttpResponse::NotFound().body("Patient not found"))
     }
 }
 
+#[derive(Serialize)]
+struct DoctorListing {
+    #[serde(flatten)]
+    doctor: doctors::DoctorRecord,
+    consultations: usize,
+    patients: usize,
+}
+
+/// A doctor with how many consultations they wrote and for how many
+/// patients; the counts identify nobody, so they are not filtered by access.
+fn doctor_listing(doctor: doctors::DoctorRecord, consultations: &[Consultation]) -> DoctorListing {
+    let own: Vec<&Consultation> = consultations.iter().filter(|c| c.doctor.crm == doctor.crm).collect();
+    let mut cpfs: Vec<&str> = own.iter().map(|c| c.patient.cpf.as_str()).collect();
+    cpfs.sort();
+    cpfs.dedup();
+    DoctorListing { consultations: own.len(), patients: cpfs.len(), doctor }
+}
+
+/// Patients visible to the user that were seen by `crm`, each with that
+/// doctor's consultations only.
+fn doctor_patients(user: &auth::User, crm: &Crm) -> Vec<PatientSummary> {
+    let mut patients: Vec<PatientSummary> = visible_patients(user).into_iter()
+        .filter_map(|mut p| {
+            p.consultations.retain(|c| c.doctor.crm == **crm);
+            (!p.consultations.is_empty()).then_some(p)
+        })
+        .collect();
+    patients.sort_by(|a, b| a.patient.nome.cmp(&b.patient.nome));
+    patients
+}
+
+async fn get_doctors(user: AuthUser) -> Result<HttpResponse> {
+    if !user.0.role.can(Permission::ReadDemographics) {
+        return Ok(not_permitted());
+    }
+    let consultations = load_consultations();
+    let listing: Vec<DoctorListing> = doctors::all(&db::open()).into_iter()
+        .map(|doctor| doctor_listing(doctor, &consultations))
+        .collect();
+    Ok(HttpResponse::Ok().json(listing))
+}
+
+async fn get_doctor(user: AuthUser, path: web::Path<Crm>) -> Result<HttpResponse> {
+    if !user.0.role.can(Permission::ReadDemographics) {
+        return Ok(not_permitted());
+    }
+    match doctors::get(&db::open(), &path) {
+        Some(doctor) => Ok(HttpResponse::Ok().json(doctor_listing(doctor, &load_consultations()))),
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "Médico não encontrado" }))),
+    }
+}
+
+async fn get_doctor_patients(req: HttpRequest, user: AuthUser, path: web::Path<Crm>) -> Result<HttpResponse> {
+    if !user.0.role.can(Permission::ReadDemographics) {
+        return Ok(not_permitted());
+    }
+    if doctors::get(&db::open(), &path).is_none() {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "Médico não encontrado" })));
+    }
+    let patients = doctor_patients(&user.0, &path);
+    audit_request(&req, &user.0, "list", patients.iter().map(|p| p.patient.cpf.as_str()));
+    Ok(HttpResponse::Ok().json(patients))
+}
+
+/// The doctor's consultations among the patients visible to the user, newest
+/// first, without the clinical sections for roles that may not read them.
+async fn get_doctor_consultations(req: HttpRequest, user: AuthUser, path: web::Path<Crm>) -> Result<HttpResponse> {
+    if !user.0.role.can(Permission::ReadDemographics) {
+        return Ok(not_permitted());
+    }
+    if doctors::get(&db::open(), &path).is_none() {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "Médico não encontrado" })));
+    }
+    let patients = doctor_patients(&user.0, &path);
+    audit_request(&req, &user.0, "list", patients.iter().map(|p| p.patient.cpf.as_str()));
+    let mut consultations: Vec<Consultation> = patients.into_iter().flat_map(|p| p.consultations).collect();
+    consultations.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    Ok(HttpResponse::Ok().json(consultations))
+}
+
+fn invalid_fields(errors: patients::FieldErrors) -> HttpResponse {
+    HttpResponse::UnprocessableEntity().json(serde_json::json!({ "error": "Dados inválidos", "fields": errors }))
+}
+
+#[derive(Deserialize)]
+struct NewPatientForm {
+    cpf: String,
+    nome: String,
+    birth_date: String,
+}
+
+async fn create_patient(req: HttpRequest, user: AuthUser, form: web::Json<NewPatientForm>) -> Result<HttpResponse> {
+    if !user.0.role.can(Permission::WriteDemographics) {
+        return Ok(not_permitted());
+    }
+    let patient = match patients::validate(&form.cpf, &form.nome, &form.birth_date) {
+        Ok(p) => p,
+        Err(errors) => return Ok(invalid_fields(errors)),
+    };
+    if let Err(e) = patients::insert(&db::open(), &patient, patients::FROM_REGISTRY) {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({ "error": e, "fields": { "cpf": e } })));
+    }
+    audit_request(&req, &user.0, "create_patient", [patient.cpf.as_str()]);
+    Ok(HttpResponse::Created().json(patient))
+}
+
+#[derive(Deserialize)]
+struct UpdatePatientForm {
+    nome: Option<String>,
+    birth_date: Option<String>,
+}
+
+async fn update_patient(req: HttpRequest, user: AuthUser, path: web::Path<Cpf>, form: web::Json<UpdatePatientForm>) -> Result<HttpResponse> {
+    if !user.0.role.can(Permission::WriteDemographics) {
+        return Ok(not_permitted());
+    }
+    let conn = db::open();
+    let Some(current) = patients::get(&conn, &path) else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "Paciente não encontrado" })));
+    };
+    let form = form.into_inner();
+    let nome = form.nome.unwrap_or_else(|| current.nome.clone());
+    let birth_date = form.birth_date.unwrap_or_else(|| current.birth_date.clone());
+    let patient = match patients::validate_update(&current, &nome, &birth_date) {
+        Ok(p) => p,
+        Err(errors) => return Ok(invalid_fields(errors)),
+    };
+    if let Err(e) = patients::update(&conn, &patient) {
+        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({ "error": e })));
+    }
+    audit_request(&req, &user.0, "update_patient", [patient.cpf.as_str()]);
+    Ok(HttpResponse::Ok().json(patient))
+}
+
+async fn delete_patient(req: HttpRequest, user: AuthUser, path: web::Path<Cpf>) -> Result<HttpResponse> {
+    if !user.0.role.can(Permission::WriteDemographics) {
+        return Ok(not_permitted());
+    }
+    let cpf = String::from(path.into_inner());
+    if load_consultations().iter().any(|c| c.patient.cpf == cpf) {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Paciente possui consultas registradas; use `mymed patient erase` para eliminação."
+        })));
+    }
+    if !patients::delete(&db::open(), &cpf) {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "Paciente não encontrado" })));
+    }
+    audit_request(&req, &user.0, "delete_patient", [cpf.as_str()]);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn verify_file(req: HttpRequest, user: AuthUser, path: web::Path<String>) -> Result<HttpResponse> {
+    if !user.0.role.can(Permission::ReadClinical) {
+        return Ok(not_permitted());
+    }
+    let filename = path.into_inner();
+    let Some(content) = find_med_file(&filename).and_then(|p| storage::read_med(&p).ok()) else {
+        return Ok(HttpResponse::NotFound().body("File not found"));
+    };
+    let conn = db::open();
+    let Some(cons) = parse_med_file(&content) else {
+        return Ok(HttpResponse::NotFound().body("File not found"));
+    };
+    let readable = group_by_patient(load_consultations()).remove(&cons.patient.cpf)
+        .is_some_and(|summary| can_read_clinical(&conn, &user.0, &summary));
+    if !readable {
+        return Ok(forbidden());
+    }
+    let status = signature::verify_content(&conn, &content);
+    audit_request(&req, &user.0, "verify", [cons.patient.cpf.as_str()]);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "filename": filename,
+        "crm": cons.doctor.crm,
+        "hash": signature::content_hash(&content),
+        "status": status,
+        "label": status.label(),
+    })))
+}
+
+/// Receives .med files as multipart/form-data. Without `?confirm=true` it
+/// only reports what each file contains and what is wrong with it.
+async fn import_files(req: HttpRequest, user: AuthUser, query: web::Query<HashMap<String, String>>, body: web::Bytes) -> Result<HttpResponse> {
+    if !user.0.role.can(Permission::WriteClinical) {
+        return Ok(not_permitted());
+    }
+    let content_type = req.headers().get(actix_web::http::header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    let uploads = match import::parse_multipart(content_type, &body) {
+        Ok(uploads) if !uploads.is_empty() => uploads,
+        Ok(_) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "Nenhum arquivo enviado." }))),
+        Err(e) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e }))),
+    };
+    let Some(crm) = user.0.crm.clone() else {
+        return Ok(not_permitted());
+    };
+    let mut previews = import::preview(&db::open(), &uploads, &load_consultations(), &crm);
+    let confirm = query.get("confirm").is_some_and(|v| v == "true" || v == "1");
+    if confirm {
+        let stored = match import::store(&db::open(), &uploads, &mut previews, &user.0.username) {
+            Ok(stored) => stored,
+            Err(e) => return Ok(HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))),
+        };
+        if !stored.is_empty() {
+            let cpfs: Vec<&str> = stored.iter().map(|c| c.patient.cpf.as_str()).collect();
+            let affected: Vec<Consultation> = load_consultations().into_iter().filter(|c| cpfs.contains(&c.patient.cpf.as_str())).collect();
+            if let Err(e) = medications::sync(&db::open(), &affected) {
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({ "error": e })));
+            }
+            doctors::sync(&db::open(), &affected);
+            audit_request(&req, &user.0, "import", cpfs);
+        }
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "confirmed": confirm, "files": previews })))
+}
+
+#[derive(Deserialize)]
+struct ConsultationForm {
+    /// Defaults to the specialty of the doctor's previous consultations.
+    especialidade: Option<String>,
+    #[serde(default)]
+    anamnese: String,
+    #[serde(default)]
+    exame_fisico: String,
+    #[serde(default)]
+    hipotese_diagnostica: String,
+    #[serde(default)]
+    conduta: String,
+}
+
+/// Writes a new consultation by the logged-in doctor. The headers come from
+/// the session and the patient record; only the clinical sections are sent.
+async fn create_consultation(req: HttpRequest, user: AuthUser, path: web::Path<Cpf>, form: web::Json<ConsultationForm>) -> Result<HttpResponse> {
+    let Some(crm) = user.0.crm.clone().filter(|_| user.0.role.can(Permission::WriteClinical)) else {
+        return Ok(not_permitted());
+    };
+    let cpf = String::from(path.into_inner());
+    let conn = db::open();
+    let consultations = load_consultations();
+    let patient = match group_by_patient(consultations.clone()).remove(&cpf) {
+        Some(mut summary) => {
+            if !can_read_clinical(&conn, &user.0, &summary) {
+                return Ok(forbidden());
+            }
+            apply_registration(&conn, &mut summary);
+            summary.patient
+        }
+        None => match patients::get(&conn, &cpf) {
+            Some(p) => p,
+            None => return Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "Paciente não encontrado" }))),
+        },
+    };
+    let especialidade = form.especialidade.clone().filter(|e| !e.trim().is_empty())
+        .or_else(|| consultations.iter().filter(|c| c.doctor.crm == crm).max_by(|a, b| a.timestamp.cmp(&b.timestamp))
+            .map(|c| c.doctor.especialidade.clone()));
+    let Some(especialidade) = especialidade else {
+        let mut errors = patients::FieldErrors::new();
+        errors.insert("especialidade", "Informe a especialidade.".to_string());
+        return Ok(invalid_fields(errors));
+    };
+    let sections = [
+        ("ANAMNESE", "anamnese", &form.anamnese),
+        ("EXAME FISICO", "exame_fisico", &form.exame_fisico),
+        ("HIPOTESE DIAGNOSTICA", "hipotese_diagnostica", &form.hipotese_diagnostica),
+        ("CONDUTA", "conduta", &form.conduta),
+    ];
+    // A header typed inside a section would move text into another block.
+    let errors: patients::FieldErrors = sections.iter()
+        .filter(|(_, _, text)| text.lines().any(|l| medfile::section_header(l).is_some()))
+        .map(|(_, field, _)| (*field, "Linhas no formato [SEÇÃO] não são permitidas no texto.".to_string()))
+        .collect();
+    if !errors.is_empty() {
+        return Ok(invalid_fields(errors));
+    }
+    let doctor = Doctor { crm, nome: user.0.nome.clone(), especialidade: especialidade.trim().to_string() };
+    let content = medfile::consultation_template(&patient, &doctor, &medfile::new_tx_id(),
+                                                 &sections.map(|(name, _, text)| (name, text.as_str())));
+    let found = diagnostics::check(&content);
+    if diagnostics::has_errors(&found) {
+        return Ok(HttpResponse::UnprocessableEntity().json(serde_json::json!({ "error": "O texto da consulta tem erros.", "diagnostics": found })));
+    }
+
+    let ip = req.peer_addr().map(|addr| addr.ip().to_string());
+    let (filename, signed) = match consult::save(&conn, &content, &user.0.username, req.path(), ip.as_deref()) {
+        Ok(saved) => saved,
+        Err(e) => return Ok(HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))),
+    };
+    let cons = parse_med_file(&content);
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "filename": filename,
+        "tx_id": cons.map(|c| c.tx_id),
+        "signed": signed,
+        "diagnostics": found,
+    })))
+}
+
+/// Parses a .med sent as the request body without storing it: the parsed
+/// consultation, its exams, the parser diagnostics and how its [CONDUTA]
+/// changes the patient's current medications.
+async fn parse_med(req: HttpRequest, user: AuthUser, body: String) -> Result<HttpResponse> {
+    if !user.0.role.can(Permission::ReadClinical) {
+        return Ok(not_permitted());
+    }
+    let found = diagnostics::check(&body);
+    let Some(cons) = parse_med_file(&body) else {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({ "consultation": null, "diagnostics": found })));
+    };
+    let history: Vec<Consultation> = load_consultations().into_iter()
+        .filter(|c| c.patient.cpf == cons.patient.cpf && c.tx_id != cons.tx_id)
+        .collect();
+    if !history.is_empty() {
+        let conn = db::open();
+        let readable = group_by_patient(history.clone()).remove(&cons.patient.cpf)
+            .is_some_and(|summary| can_read_clinical(&conn, &user.0, &summary));
+        if !readable {
+            return Ok(forbidden());
+        }
+        audit_request(&req, &user.0, "parse", [cons.patient.cpf.as_str()]);
+    }
+    let before = medications::current(&history);
+    let mut after = before.clone();
+    let changes = medications::apply_conduta(&mut after, &cons.conduta);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "consultation": cons,
+        "exams": medfile::parse_exams(&body),
+        "diagnostics": found,
+        "current_medications": before,
+        "medication_changes": changes,
+        "resulting_medications": after,
+    })))
+}
+
+/// Editor suggestions: `?ctx=directive|drug|exam|cid&prefix=...`.
+async fn complete_terms(user: AuthUser, query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
+    if !user.0.role.can(Permission::WriteClinical) {
+        return Ok(not_permitted());
+    }
+    let Some(ctx) = query.get("ctx").and_then(|c| complete::Context::parse(c)) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "ctx deve ser directive, drug, exam ou cid" })));
+    };
+    let prefix = query.get("prefix").map(String::as_str).unwrap_or_default();
+    let suggestions = complete::complete(&db::open(), &medfile::load_med_files(), user.0.crm.as_deref(), ctx, prefix);
+    Ok(HttpResponse::Ok().json(suggestions))
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    username: String,
+    password: String,
+}
+
+async fn login(form: web::Json<LoginForm>) -> Result<HttpResponse> {
+    match auth::login(&db::open(), &form.username, &form.password) {
+        Some((token, user)) => {
+            let cookie = Cookie::build(auth::SESSION_COOKIE, token).path("/").http_only(true).same_site(SameSite::Strict).finish();
+            Ok(HttpResponse::Ok().cookie(cookie).json(user))
+        }
+        None => Ok(HttpResponse::Unauthorized().json(serde_json::json!({ "error": "Usuário ou senha inválidos" }))),
+    }
+}
+
+async fn logout(req: HttpRequest) -> Result<HttpResponse> {
+    if let Some(cookie) = req.cookie(auth::SESSION_COOKIE) {
+        auth::logout(&db::open(), cookie.value());
+    }
+    let mut expired = Cookie::build(auth::SESSION_COOKIE, "").path("/").finish();
+    expired.make_removal();
+    Ok(HttpResponse::Ok().cookie(expired).finish())
+}
+
+async fn me(user: AuthUser) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(user.0))
+}
+
 async fn run_web() -> std::io::Result<()> {
     setup_db();
     let consultations = load_consultations();
-    process_medications(&consultations);
+    medications::sync(&db::open(), &consultations).map_err(std::io::Error::other)?;
+    doctors::sync(&db::open(), &consultations);
+    access::sync_contract_grants(&db::open(), &medfile::load_med_files());
 
     HttpServer::new(|| {
         App::new()
+            .route("/login", web::post().to(login))
+            .route("/logout", web::post().to(logout))
+            .route("/me", web::get().to(me))
+            .app_data(web::JsonConfig::default().error_handler(|err, _| {
+                let response = HttpResponse::BadRequest().json(serde_json::json!({ "error": format!("JSON inválido: {}", err) }));
+                actix_web::error::InternalError::from_response(err, response).into()
+            }))
             .route("/patients", web::get().to(get_patients))
+            .route("/patients", web::post().to(create_patient))
+            .route("/patients/{cpf}", web::put().to(update_patient))
+            .route("/patients/{cpf}", web::delete().to(delete_patient))
             .route("/search", web::get().to(search_patients))
             .route("/patient/{cpf}", web::get().to(get_patient))
-            .service(afs::Files::new("/", ".").index_file("index.html"))
+            .route("/patient/{cpf}/consultations", web::post().to(create_consultation))
+            .route("/doctors", web::get().to(get_doctors))
+            .route("/doctors/{crm}", web::get().to(get_doctor))
+            .route("/doctors/{crm}/patients", web::get().to(get_doctor_patients))
+            .route("/doctors/{crm}/consultations", web::get().to(get_doctor_consultations))
+            .route("/med/parse", web::post().to(parse_med))
+            .route("/complete", web::get().to(complete_terms))
+            .route("/verify/{filename}", web::get().to(verify_file))
+            .service(web::resource("/import")
+                .app_data(web::PayloadConfig::new(16 * 1024 * 1024))
+                .route(web::post().to(import_files)))
+            .service(afs::Files::new("/", ".").index_file("index.html")
+                .path_filter(|path, _| path.as_os_str().is_empty() || path.extension().is_some_and(|ext| ext == "html")))
     })
     .bind("127.0.0.1:8080")?
     .run()
@@ -418,81 +1013,70 @@ async fn main() -> std::io::Result<()> {
     } else {
         // CLI
         let cli = Cli::parse();
+        let json = cli.json;
         let result: std::io::Result<()> = match cli.command {
-            Some(Commands::New { sub: NewSub::Patient }) => {
-                println!("Digite CPF:");
-                let mut cpf = String::new();
-                std::io::stdin().read_line(&mut cpf).unwrap();
-                let cpf = cpf.trim().to_string();
-                println!("Digite nome:");
-                let mut nome = String::new();
-                std::io::stdin().read_line(&mut nome).unwrap();
-                let nome = nome.trim().to_string();
-                println!("Digite data nascimento (DD/MM/AAAA):");
-                let mut birth_input = String::new();
-                std::io::stdin().read_line(&mut birth_input).unwrap();
-                let birth_input = birth_input.trim();
-                // validate format
-                if !regex::Regex::new(r"^\d{2}/\d{2}/\d{4}$").unwrap().is_match(birth_input) {
-                    println!("Formato inválido. Use DD/MM/AAAA.");
-                    return Ok(());
-                }
-                // convert to YYYY-MM-DD
-                let parts: Vec<&str> = birth_input.split('/').collect();
-                let birth_date = format!("{}-{}-{}", parts[2], parts[1], parts[0]);
-                // call subprocess
-                let status = Command::new("./target/debug/mymed_patient_manager")
-                    .args(&["add", "--cpf", &cpf, "--nome", &nome, "--birth-date", &birth_date])
-                    .status()
-                    .expect("Failed to execute mymed_patient_manager");
-                if status.success() {
-                    println!("Paciente cadastrado com sucesso.");
-                } else {
-                    println!("Erro ao cadastrar paciente.");
+            Some(Commands::New { sub: NewSub::Patient { cpf, nome, nascimento } }) => {
+                let cpf = flag_or_prompt(json, "cpf", cpf, "Digite CPF:");
+                let nome = flag_or_prompt(json, "nome", nome, "Digite nome:");
+                let nascimento = flag_or_prompt(json, "nascimento", nascimento, "Digite data nascimento (DD/MM/AAAA):");
+                let patient = match patients::validate(&cpf, &nome, &nascimento) {
+                    Ok(patient) => patient,
+                    Err(errors) => {
+                        let message: Vec<String> = errors.iter().map(|(field, e)| format!("{}: {}", field, e)).collect();
+                        report(json, serde_json::json!({ "error": "Dados inválidos", "fields": errors }), message.join("\n"));
+                        std::process::exit(1);
+                    }
+                };
+                setup_db();
+                if let Err(e) = patients::insert(&db::open(), &patient, patients::FROM_REGISTRY) {
+                    fail(json, e);
                 }
+                cli::audit("create_patient", Some(&patient.cpf), "new patient");
+                report(json, serde_json::json!(patient), "Paciente cadastrado com sucesso.");
                 Ok(())
             }
-            Some(Commands::Import { sub: ImportSub::Patient }) => {
-                println!("Digite caminho para arquivo .med:");
-                let mut path = String::new();
-                std::io::stdin().read_line(&mut path).unwrap();
-                let path = path.trim();
-                let content = match std::fs::read_to_string(path) {
-                    Ok(c) => c,
-                    Err(_) => {
-                        println!("Arquivo não encontrado.");
-                        return Ok(());
-                    }
+            Some(Commands::Import { sub: ImportSub::Patient { file, yes } }) => {
+                let path = flag_or_prompt(json, "file", file, "Digite caminho para arquivo .med:");
+                let Ok(content) = storage::read_med(std::path::Path::new(&path)) else {
+                    fail(json, "Arquivo não encontrado.");
                 };
-                let patient = parse_patient_from_med(&content);
-                if let Some(p) = patient {
+                let Some(p) = parse_patient_from_med(&content) else {
+                    fail(json, "Falha ao parsear arquivo .med");
+                };
+                if !json {
                     println!("Dados do paciente:");
                     println!("CPF: {}", p.cpf);
                     println!("Nome: {}", p.nome);
                     println!("Data Nascimento: {}", p.birth_date);
-                    println!("Confirmar importação? (s/n)");
-                    let mut confirm = String::new();
-                    std::io::stdin().read_line(&mut confirm).unwrap();
-                    if confirm.trim().to_lowercase() == "s" {
-                        let status = Command::new("./target/debug/mymed_patient_manager")
-                            .args(&["add", "--cpf", &p.cpf, "--nome", &p.nome, "--birth-date", &p.birth_date])
-                            .status()
-                            .expect("Failed");
-                        if status.success() {
-                            println!("Paciente importado.");
-                        }
-                    }
-                } else {
-                    println!("Falha ao parsear arquivo .med");
                 }
+                if !confirm(json, yes, "Confirmar importação?") {
+                    report(json, serde_json::json!({ "patient": p, "imported": false }), "Importação cancelada.");
+                    return Ok(());
+                }
+                setup_db();
+                if let Err(e) = patients::insert(&db::open(), &p, patients::FROM_MEDFILE) {
+                    fail(json, e);
+                }
+                cli::audit("import_patient", Some(&p.cpf), "import patient");
+                report(json, serde_json::json!({ "patient": p, "imported": true }), "Paciente importado.");
                 Ok(())
             }
             Some(Commands::List { sub: ListSub::Patients }) => {
-                let output = Command::new("./target/debug/mymed_patient_manager")
-                    .args(&["list"])
-                    .output()
-                    .expect("Failed");
-                println!("{}", String::from_utf8_lossy(&output.stdout));
+                setup_db();
+                let mut all = patients::all(&db::open());
+                all.sort_by(|a, b| a.nome.cmp(&b.nome));
+                if json {
+                    report(json, serde_json::json!(all), "");
+                } else if atty::is(atty::Stream::Stdout) && interactive() {
+                    Command::new("./target/debug/mymed_patient_manager")
+                        .args(["list"])
+                        .status()
+                        .expect("Failed");
+                } else {
+                    for p in all {
+                        println!("{} - {} - {}", p.nome, p.cpf, p.birth_date);
+                    }
+                }
                 Ok(())
             }
             Some(Commands::Web) => {
@@ -502,40 +1086,351 @@ async fn main() -> std::io::Result<()> {
             Some(Commands::Upgrade) => {
                 // check latest release
                 let output = std::process::Command::new("gh")
-                    .args(&["release", "list", "--json", "tagName", "--limit", "1"])
+                    .args(["release", "list", "--json", "tagName", "--limit", "1"])
                     .output()
                     .expect("Failed to run gh");
-                let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
-                let latest_tag = json[0]["tagName"].as_str().unwrap();
+                let releases: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+                let latest_tag = releases[0]["tagName"].as_str().unwrap();
                 let current_version = env!("CARGO_PKG_VERSION");
                 if latest_tag > current_version {
-                    println!("Nova versão disponível: {}. Atualizando...", latest_tag);
+                    if !json {
+                        println!("Nova versão disponível: {}. Atualizando...", latest_tag);
+                    }
                     // download
                     let download_output = std::process::Command::new("gh")
-                        .args(&["release", "download", latest_tag, "--pattern", "mymed.gz"])
+                        .args(["release", "download", latest_tag, "--pattern", "mymed.gz"])
                         .output()
                         .expect("Failed to download");
                     if download_output.status.success() {
                         // extract
                         std::process::Command::new("gunzip")
-                            .args(&["mymed.gz"])
+                            .args(["mymed.gz"])
                             .status()
                             .expect("Failed to gunzip");
                         // make executable and move
                         std::process::Command::new("chmod")
-                            .args(&["+x", "mymed"])
+                            .args(["+x", "mymed"])
                             .status()
                             .expect("Failed to chmod");
                         std::process::Command::new("sudo")
-                            .args(&["mv", "mymed", "/usr/local/bin/mymed"])
+                            .args(["mv", "mymed", "/usr/local/bin/mymed"])
                             .status()
                             .expect("Failed to move");
-                        println!("Atualização concluída!");
+                        report(json, serde_json::json!({ "current_version": current_version, "latest": latest_tag, "updated": true }),
+                               "Atualização concluída!");
                     } else {
-                        println!("Erro ao baixar atualização.");
+                        fail(json, "Erro ao baixar atualização.");
+                    }
+                } else {
+                    report(json, serde_json::json!({ "current_version": current_version, "latest": latest_tag, "updated": false }),
+                           format!("Você já tem a versão mais recente: {}", current_version));
+                }
+                Ok(())
+            }
+            Some(Commands::Keys { sub: KeysSub::Generate { crm } }) => {
+                setup_db();
+                let conn = db::open();
+                match signature::generate_key(&conn, &crm) {
+                    Ok(public_key) => report(json, serde_json::json!({ "crm": crm, "public_key": public_key }),
+                                             format!("Chave gerada para o CRM {}.\nChave pública: {}", crm, public_key)),
+                    Err(e) => fail(json, e),
+                }
+                Ok(())
+            }
+            Some(Commands::Sign { path }) => {
+                let Ok(content) = storage::read_med(std::path::Path::new(&path)) else {
+                    fail(json, "Arquivo não encontrado.");
+                };
+                setup_db();
+                let conn = db::open();
+                match ledger::seal_to_file(&conn, &content, std::path::Path::new(&path)) {
+                    Ok(signed) => {
+                        cli::audit("sign", parse_med_file(&content).map(|c| c.patient.cpf).as_deref(), "sign");
+                        report(json, serde_json::json!({ "file": path, "hash": signature::content_hash(&signed) }),
+                               "Arquivo assinado e registrado no ledger.");
+                    }
+                    Err(e) => fail(json, e),
+                }
+                Ok(())
+            }
+            Some(Commands::User { sub: UserSub::Add { username, nome, role, crm } }) => {
+                let Some(role) = auth::Role::parse(&role) else {
+                    fail(json, "Papel inválido. Use doctor, receptionist ou admin.");
+                };
+                // Without a terminal the password is the first line of stdin.
+                let password = if interactive() {
+                    let Some(password) = cli::password("Digite a senha:") else {
+                        fail(json, "Usuário não criado.");
+                    };
+                    if cli::password("Confirme a senha:").as_ref() != Some(&password) {
+                        fail(json, "As senhas não conferem; usuário não criado.");
                     }
+                    password
                 } else {
-                    println!("Você já tem a versão mais recente: {}", current_version);
+                    let mut password = String::new();
+                    std::io::stdin().read_line(&mut password).unwrap();
+                    password.trim_end_matches(['\r', '\n']).to_string()
+                };
+                setup_db();
+                match auth::create_user(&db::open(), &username, &nome, &password, role, crm.as_deref()) {
+                    Ok(()) => report(json, serde_json::json!({ "username": username, "nome": nome, "role": role, "crm": crm }),
+                                     format!("Usuário {} criado.", username)),
+                    Err(e) => fail(json, e),
+                }
+                Ok(())
+            }
+            Some(Commands::User { sub: UserSub::List }) => {
+                setup_db();
+                let users = auth::users(&db::open());
+                if json {
+                    report(json, serde_json::json!(users), "");
+                    return Ok(());
+                }
+                for u in users {
+                    println!("{} - {} ({}{})", u.username, u.nome, u.role.as_str(),
+                             u.crm.map(|c| format!(", CRM {}", c)).unwrap_or_default());
+                }
+                Ok(())
+            }
+            Some(Commands::Grant { cpf, crm, days }) => {
+                setup_db();
+                let expires_at = days.map(|d| (chrono::Utc::now().date_naive() + chrono::Duration::days(d)).format("%Y-%m-%d").to_string());
+                access::grant(&db::open(), &cpf, &crm, expires_at.as_deref());
+                cli::audit("grant", Some(&cpf), "grant");
+                let message = match &expires_at {
+                    Some(date) => format!("Acesso concedido ao CRM {} até {}.", crm, date),
+                    None => format!("Acesso concedido ao CRM {}.", crm),
+                };
+                report(json, serde_json::json!({ "cpf": cpf, "crm": crm, "expires_at": expires_at }), message);
+                Ok(())
+            }
+            Some(Commands::Revoke { cpf, crm }) => {
+                setup_db();
+                let revoked = access::revoke(&db::open(), &cpf, &crm);
+                if revoked > 0 {
+                    cli::audit("revoke", Some(&cpf), "revoke");
+                }
+                let message = if revoked > 0 {
+                    format!("Acesso do CRM {} revogado.", crm)
+                } else {
+                    format!("Nenhum acesso ativo do CRM {} para este paciente.", crm)
+                };
+                report(json, serde_json::json!({ "cpf": cpf, "crm": crm, "revoked": revoked > 0 }), message);
+                Ok(())
+            }
+            Some(Commands::Grants { cpf }) => {
+                setup_db();
+                let grants = access::grants(&db::open(), &cpf);
+                if json {
+                    report(json, serde_json::json!(grants), "");
+                    return Ok(());
+                }
+                for g in grants {
+                    let status = if g.is_active() { "ativo" } else { "inativo" };
+                    println!("CRM {} - {} (desde {}, até {}, origem {}){}", g.crm, status, g.granted_at,
+                             g.expires_at.as_deref().unwrap_or("sem prazo"), g.source,
+                             g.revoked_at.map(|r| format!(", revogado em {}", r)).unwrap_or_default());
+                }
+                Ok(())
+            }
+            Some(Commands::Patient { sub: PatientSub::Export { cpf, out } }) => {
+                setup_db();
+                let conn = db::open();
+                let out = out.unwrap_or_else(|| format!("paciente_{}", cpf.replace(['.', '-'], "")));
+                match lgpd::export_patient(&conn, &cpf, std::path::Path::new(&out)) {
+                    Ok(n) => {
+                        cli::audit("patient_export", Some(&cpf), "patient export");
+                        report(json, serde_json::json!({ "cpf": cpf, "out": out, "consultations": n }),
+                               format!("{} consultas exportadas para {}.", n, out));
+                    }
+                    Err(e) => fail(json, e),
+                }
+                Ok(())
+            }
+            Some(Commands::Patient { sub: PatientSub::Erase { cpf, force } }) => {
+                setup_db();
+                let conn = db::open();
+                match lgpd::erase_patient(&conn, &cpf, force) {
+                    Ok(erasure) if erasure.files == 0 && erasure.rows == 0 => fail(json, format!("Paciente {} não encontrado.", cpf)),
+                    Ok(erasure) => {
+                        cli::audit("patient_erase", Some(&cpf), "patient erase");
+                        report(json, serde_json::json!({ "cpf": cpf, "files": erasure.files, "rows": erasure.rows, "ledger_chains": erasure.chains }),
+                               format!("Paciente {} apagado: {} arquivos .med e {} registros; {} cadeia(s) do ledger encerrada(s).",
+                                       cpf, erasure.files, erasure.rows, erasure.chains));
+                    }
+                    Err(e) => fail(json, e),
+                }
+                Ok(())
+            }
+            Some(Commands::Export { sub: ExportSub::Anonymized { out } }) => {
+                setup_db();
+                let conn = db::open();
+                let records = export::anonymized(&conn, &medfile::load_med_files());
+                let lines: Vec<String> = records.iter().map(|r| serde_json::to_string(r).unwrap()).collect();
+                match out {
+                    Some(path) => {
+                        std::fs::write(&path, lines.join("\n") + "\n")?;
+                        report(json, serde_json::json!({ "out": path, "consultations": records.len() }),
+                               format!("{} consultas exportadas para {}.", records.len(), path));
+                    }
+                    None => println!("{}", lines.join("\n")),
+                }
+                cli::audit("export_anonymized", None, "export anonymized");
+                Ok(())
+            }
+            Some(command @ (Commands::Encrypt | Commands::Decrypt)) => {
+                let encrypted = matches!(command, Commands::Encrypt);
+                setup_db();
+                let conn = db::open();
+                if let Err(e) = storage::unlock() {
+                    fail(json, e);
+                }
+                if encrypted {
+                    storage::set_setting(&conn, "encryption", "on");
+                }
+                let mut files = 0;
+                for entry in walkdir::WalkDir::new(medfile::medfiles_dir()).into_iter().filter_map(|e| e.ok()) {
+                    if entry.path().extension().and_then(|s| s.to_str()) != Some("med") {
+                        continue;
+                    }
+                    match storage::convert_med(entry.path(), encrypted) {
+                        Ok(true) => files += 1,
+                        Ok(false) => {}
+                        Err(e) => fail(json, format!("{}: {}", entry.path().display(), e)),
+                    }
+                }
+                let rows = storage::convert_patients(&conn, encrypted).unwrap_or_else(|e| fail(json, e));
+                let meds = storage::convert_medications(&conn, encrypted).unwrap_or_else(|e| fail(json, e));
+                if !encrypted {
+                    storage::set_setting(&conn, "encryption", "off");
+                }
+                report(json, serde_json::json!({ "encrypted": encrypted, "files": files, "patients": rows, "medications": meds }),
+                       format!("{} arquivos .med, {} pacientes e {} medicações em uso {}.", files, rows, meds,
+                               if encrypted { "criptografados" } else { "descriptografados" }));
+                Ok(())
+            }
+            Some(Commands::Audit { cpf, user, report: access_report }) => {
+                setup_db();
+                let conn = db::open();
+                if access_report {
+                    let Some(cpf) = cpf else {
+                        fail(json, "Informe --cpf para o relatório.");
+                    };
+                    let lines = audit::access_report(&conn, &cpf);
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "cpf": cpf, "accesses": lines })).unwrap());
+                    } else {
+                        println!("Acessos aos dados do paciente {}:", cpf);
+                        for l in lines {
+                            let who = match (&l.nome, &l.crm) {
+                                (Some(nome), Some(crm)) => format!("{} (CRM {})", nome, crm),
+                                (Some(nome), None) => format!("{} ({})", nome, l.role.as_deref().unwrap_or("")),
+                                _ => l.username.clone(),
+                            };
+                            println!("- {}: {} x{} (de {} a {})", who, l.action, l.count, l.first_access, l.last_access);
+                        }
+                    }
+                    return Ok(());
+                }
+                let entries = audit::query(&conn, cpf.as_deref(), user.as_deref());
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+                } else {
+                    for e in entries {
+                        println!("{} {} {} {} {} {}", e.timestamp, e.username, e.action, e.cpf.as_deref().unwrap_or("-"),
+                                 e.route, e.ip.as_deref().unwrap_or("-"));
+                    }
+                }
+                Ok(())
+            }
+            Some(Commands::Amend { tx }) => {
+                setup_db();
+                let files = medfile::load_med_files();
+                let parsed: Vec<_> = files.iter().filter_map(|(path, content)| parse_med_file(content).map(|c| (path, content, c))).collect();
+                let Some((path, content, _)) = parsed.iter().find(|(_, _, c)| c.tx_id == tx && c.amends.is_none()) else {
+                    fail(json, format!("Transação {} não encontrada.", tx));
+                };
+                // The next number whose file and transaction are both free: an
+                // earlier amendment may have been renamed or may not parse.
+                let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("consulta");
+                let mut n = parsed.iter().filter(|(_, _, c)| c.amends.as_deref() == Some(tx.as_str())).count() + 1;
+                while path.with_file_name(format!("{}_adendo_{}.med", stem, n)).exists()
+                    || parsed.iter().any(|(_, _, c)| c.tx_id == format!("{}-a{}", tx, n)) {
+                    n += 1;
+                }
+                let target = path.with_file_name(format!("{}_adendo_{}.med", stem, n));
+                let Some(template) = medfile::amendment_template(content, &format!("{}-a{}", tx, n)) else {
+                    fail(json, format!("{}: sem bloco [PATIENT]/[DOCTOR] antes de [TRANSACTION]; adendo não criado.", path.display()));
+                };
+                storage::write_med(&target, &template)?;
+                cli::audit("amend", parse_med_file(content).map(|c| c.patient.cpf).as_deref(), "amend");
+                report(json, serde_json::json!({ "file": target, "tx_id": format!("{}-a{}", tx, n), "amends": tx }),
+                       format!("Adendo criado em {}\nPreencha [ADENDO] e [CONDUTA] e sele com `mymed sign`.", target.display()));
+                Ok(())
+            }
+            Some(Commands::Check { paths }) => {
+                let files: Vec<(std::path::PathBuf, String)> = if paths.is_empty() {
+                    medfile::load_med_files()
+                } else {
+                    paths.iter().map(|p| {
+                        let path = std::path::PathBuf::from(p);
+                        let content = storage::read_med(&path).unwrap_or_else(|e| fail(json, format!("{}: {}", p, e)));
+                        (path, content)
+                    }).collect()
+                };
+                let mut results: Vec<(String, Vec<diagnostics::Diagnostic>)> = files.iter()
+                    .map(|(path, content)| (path.display().to_string(), diagnostics::check(content)))
+                    .collect();
+                if paths.is_empty() {
+                    setup_db();
+                    let in_files: Vec<String> = files.iter().filter_map(|(_, c)| medfile::parse_med_file(c)).map(|c| c.patient.cpf).collect();
+                    let registry: Vec<diagnostics::Diagnostic> = patients::all(&db::open()).iter()
+                        .filter(|p| !in_files.contains(&p.cpf))
+                        .filter_map(|p| diagnostics::check_registered_cpf(&p.cpf, &p.nome))
+                        .collect();
+                    if !registry.is_empty() {
+                        results.push(("cadastro".to_string(), registry));
+                    }
+                }
+                let errors = results.iter().any(|(_, found)| diagnostics::has_errors(found));
+                if json {
+                    let report: Vec<_> = results.iter().map(|(file, found)| serde_json::json!({ "file": file, "diagnostics": found })).collect();
+                    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+                } else {
+                    for (file, found) in &results {
+                        for d in found {
+                            println!("{}:{}{}", file, if d.line == 0 { " " } else { "" }, d.describe());
+                        }
+                    }
+                    let count: usize = results.iter().map(|(_, found)| found.len()).sum();
+                    println!("{} arquivos verificados, {} problemas.", results.len(), count);
+                }
+                if errors {
+                    std::process::exit(1);
+                }
+                Ok(())
+            }
+            Some(Commands::Consult { sub: ConsultSub::New { cpf, crm } }) => {
+                let Some(crm) = crm.map(String::from).or_else(|| std::env::var("MYMED_CRM").ok().filter(|c| !c.is_empty())) else {
+                    fail(json, "Informe --crm ou defina MYMED_CRM.");
+                };
+                let crm = Crm::parse(&crm).unwrap_or_else(|e| fail(json, e));
+                consult_new(&cpf, &crm, json)
+            }
+            Some(Commands::VerifyChain { cpf }) => {
+                setup_db();
+                let conn = db::open();
+                let issues = ledger::verify_chain(&conn, &cpf);
+                let transactions = ledger::entries(&conn, &cpf).len();
+                let intact = !issues.iter().any(|i| i.is_tampering());
+                let mut message = format!("Transações registradas: {}", transactions);
+                for issue in &issues {
+                    message.push_str(&format!("\n- {}", issue.describe()));
+                }
+                message.push_str(if intact { "\nCadeia íntegra." } else { "\nCadeia comprometida." });
+                report(json, serde_json::json!({ "cpf": cpf, "transactions": transactions, "issues": issues, "intact": intact }), message);
+                if !intact {
+                    std::process::exit(1);
                 }
                 Ok(())
             }
@@ -0,0 +1,417 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use walkdir::WalkDir;
+
+use crate::cpf::Cpf;
+use crate::crm::Crm;
+use crate::{db, patients, storage};
+
+pub const MEDFILES_DIR: &str = "/home/woulschneider/petridish/mymed/medfiles";
+
+/// The medfiles tree, overridable through `MYMED_MEDFILES`.
+pub fn medfiles_dir() -> String {
+    std::env::var("MYMED_MEDFILES").unwrap_or_else(|_| MEDFILES_DIR.to_string())
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Patient {
+    pub cpf: String,
+    pub nome: String,
+    pub birth_date: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Doctor {
+    pub crm: String,
+    pub nome: String,
+    pub especialidade: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Consultation {
+    pub patient: Patient,
+    pub doctor: Doctor,
+    pub tx_id: String,
+    pub timestamp: String,
+    pub filename: String,
+    pub hipotese_diagnostica: String,
+    pub conduta: String,
+    /// Transaction corrected by this file when it is an amendment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amends: Option<String>,
+    /// Amendments filed against this consultation, filled by `link_amendments`.
+    #[serde(default)]
+    pub amended_by: Vec<String>,
+}
+
+pub fn parse_med_file(content: &str) -> Option<Consultation> {
+    let patient_re = Regex::new(r"(?s)\[PATIENT\]\s*(.*?)\[DOCTOR\]").unwrap();
+    let doctor_re = Regex::new(r"(?s)\[DOCTOR\]\s*(.*?)\[TRANSACTION\]").unwrap();
+    let transaction_re = Regex::new(r"(?s)\[TRANSACTION\]\s*(.*?)\[CONTRACT_CONDITIONS\]").unwrap();
+    let diagnostica_re = Regex::new(r"(?s)\[HIPOTESE DIAGNOSTICA\]\s*(.*?)(?:\n\n|\n\[|$)").unwrap();
+    let conduta_re = Regex::new(r"(?s)\[CONDUTA\]\s*(.*?)(?:\n\n|\n\[|$)").unwrap();
+
+    let patient_section = patient_re.captures(content)?.get(1)?.as_str();
+    let doctor_section = doctor_re.captures(content)?.get(1)?.as_str();
+    let transaction_section = transaction_re.captures(content)?.get(1)?.as_str();
+    let diagnostica = diagnostica_re.captures(content).map_or("", |c| c.get(1).map_or("", |m| m.as_str().trim()));
+    let conduta = conduta_re.captures(content).map_or("", |c| c.get(1).map_or("", |m| m.as_str().trim()));
+
+    let cpf = Cpf::normalize(&extract_field(patient_section, "CPF:")?);
+    let nome = extract_field(patient_section, "Nome:")?;
+    let idade_str = extract_field(patient_section, "Idade:")?;
+    let idade_num: i32 = idade_str.parse().unwrap_or(0);
+    let today = chrono::Utc::now().date_naive();
+    let birth = today - chrono::Duration::days(idade_num as i64 * 365);
+    let birth_date = birth.format("%Y-%m-%d").to_string();
+
+    let crm = Crm::normalize(&extract_field(doctor_section, "CRM:")?);
+    let doc_nome = extract_field(doctor_section, "Nome:")?;
+    let especialidade = extract_field(doctor_section, "Especialidade:")?;
+
+    let tx_id = extract_field(transaction_section, "ID:").unwrap_or_default();
+    let timestamp = extract_field(transaction_section, "Timestamp:")?;
+    let amends = match extract_field(transaction_section, "Type:") {
+        Some(kind) if kind.eq_ignore_ascii_case("amendment") => extract_field(transaction_section, "Amends:"),
+        _ => None,
+    };
+
+    Some(Consultation {
+        patient: Patient { cpf, nome, birth_date },
+        doctor: Doctor { crm, nome: doc_nome, especialidade },
+        tx_id,
+        timestamp,
+        filename: "".to_string(), // will set later
+        hipotese_diagnostica: diagnostica.to_string(),
+        conduta: conduta.to_string(),
+        amends,
+        amended_by: Vec::new(),
+    })
+}
+
+impl Consultation {
+    /// Drops the clinical sections, leaving when and with whom the visit happened.
+    pub fn without_clinical_data(self) -> Consultation {
+        Consultation {
+            hipotese_diagnostica: String::new(),
+            conduta: String::new(),
+            ..self
+        }
+    }
+}
+
+/// Sorts consultations by timestamp, placing each amendment right after the
+/// transaction it amends so its corrections apply at that point in history.
+pub fn sort_chronologically(consultations: &mut [Consultation]) {
+    let originals: HashMap<String, String> = consultations.iter()
+        .filter(|c| c.amends.is_none())
+        .map(|c| (c.tx_id.clone(), c.timestamp.clone()))
+        .collect();
+    consultations.sort_by_cached_key(|c| match c.amends.as_ref().and_then(|tx| originals.get(tx)) {
+        Some(original) => (original.clone(), 1, c.timestamp.clone()),
+        None => (c.timestamp.clone(), 0, String::new()),
+    });
+}
+
+/// Records on each consultation the amendments that reference it.
+pub fn link_amendments(consultations: &mut [Consultation]) {
+    let links: Vec<(String, String)> = consultations.iter()
+        .filter_map(|c| c.amends.clone().map(|tx| (tx, c.tx_id.clone())))
+        .collect();
+    for c in consultations.iter_mut() {
+        c.amended_by = links.iter().filter(|(tx, _)| *tx == c.tx_id).map(|(_, by)| by.clone()).collect();
+    }
+}
+
+/// Builds the text of an amendment to `original`, keeping its [PATIENT] and
+/// [DOCTOR] blocks and leaving [ADENDO]/[CONDUTA] for the doctor to fill.
+pub fn amendment_template(original: &str, tx_id: &str) -> Option<String> {
+    let cons = parse_med_file(original)?;
+    let patient_re = Regex::new(r"(?s)(\[PATIENT\].*?)\[TRANSACTION\]").unwrap();
+    let header = patient_re.captures(original)?.get(1)?.as_str().trim_end();
+    Some(format!(
+        "{}\n\n[TRANSACTION]\nID: {}\nType: amendment\nAmends: {}\nTimestamp: {}\nHash: pendente\nSignature: pendente\n\n\
+         [CONTRACT_CONDITIONS]\n- Adendo à transação {}; o registro original permanece inalterado.\n\n\
+         [CONTENT]\n\n[ADENDO]\n\n[CONDUTA]\n",
+        header, tx_id, cons.tx_id, chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ"), cons.tx_id
+    ))
+}
+
+/// Clinical sections of a consultation, in the order they are written.
+pub const CLINICAL_SECTIONS: &[&str] = &["ANAMNESE", "EXAME FISICO", "HIPOTESE DIAGNOSTICA", "CONDUTA"];
+
+/// Transaction ID for a consultation written now.
+pub fn new_tx_id() -> String {
+    format!("tx{}", chrono::Utc::now().format("%Y%m%d%H%M%S%3f"))
+}
+
+/// Builds a new consultation of `patient` by `doctor`, with `sections` as
+/// `(name, text)` for the clinical sections; missing ones are left empty.
+pub fn consultation_template(patient: &Patient, doctor: &Doctor, tx_id: &str, sections: &[(&str, &str)]) -> String {
+    let mut out = format!(
+        "[PATIENT]\nCPF: {}\nNome: {}\nIdade: {}\n\n[DOCTOR]\nCRM: {}\nNome: {}\nEspecialidade: {}\n\n\
+         [TRANSACTION]\nID: {}\nTimestamp: {}\nHash: pendente\nSignature: pendente\n\n\
+         [CONTRACT_CONDITIONS]\n- Paciente consente com o tratamento.\n- Médico certifica o diagnóstico.\n\
+         - Transação imutável após assinatura.\n- Acesso controlado pelo paciente.\n\n[CONTENT]\n",
+        patient.cpf, patient.nome, calculate_age(&patient.birth_date), doctor.crm, doctor.nome, doctor.especialidade,
+        tx_id, chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+    );
+    out.push_str(&clinical_sections(sections));
+    out
+}
+
+/// The clinical sections as written after [CONTENT], from `(name, text)`
+/// pairs; missing ones are left empty.
+pub fn clinical_sections(sections: &[(&str, &str)]) -> String {
+    let mut out = String::new();
+    for name in CLINICAL_SECTIONS {
+        let text = sections.iter().find(|(n, _)| n == name).map_or("", |(_, t)| t.trim());
+        out.push_str(&format!("\n[{}]\n{}\n", name, text));
+    }
+    out
+}
+
+/// Lowercases and strips Portuguese accents, for comparing names and terms.
+pub fn fold(text: &str) -> String {
+    text.to_lowercase().chars().map(|c| match c {
+        'á' | 'à' | 'â' | 'ã' | 'ä' => 'a',
+        'é' | 'è' | 'ê' | 'ë' => 'e',
+        'í' | 'ì' | 'î' | 'ï' => 'i',
+        'ó' | 'ò' | 'ô' | 'õ' | 'ö' => 'o',
+        'ú' | 'ù' | 'û' | 'ü' => 'u',
+        'ç' => 'c',
+        'ñ' => 'n',
+        c => c,
+    }).collect()
+}
+
+/// File name stem for a patient's consultations, e.g. `cleusa_ferreira_de_sa`.
+pub fn file_stem(nome: &str) -> String {
+    let folded: String = fold(nome).chars().map(|c| if c.is_ascii_alphanumeric() { c } else { ' ' }).collect();
+    folded.split_whitespace().collect::<Vec<_>>().join("_")
+}
+
+/// Path in the medfiles directory for a new file named `filename` (base name
+/// only), with a numeric suffix when that name is taken.
+pub fn available_path(filename: &str) -> std::path::PathBuf {
+    let name = std::path::Path::new(filename).file_name().and_then(|n| n.to_str()).unwrap_or("consulta.med");
+    let stem = name.strip_suffix(".med").unwrap_or(name);
+    let dir = std::path::PathBuf::from(medfiles_dir());
+    let mut path = dir.join(format!("{}.med", stem));
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("{}_{}.med", stem, n));
+        n += 1;
+    }
+    path
+}
+
+pub fn extract_field(section: &str, key: &str) -> Option<String> {
+    for line in section.lines() {
+        if let Some(value) = line.strip_prefix(key) {
+            return Some(value.trim().to_string());
+        }
+    }
+    None
+}
+
+/// Returns the section name if `line` is a `[SECTION]` header.
+pub fn section_header(line: &str) -> Option<&str> {
+    let line = line.trim();
+    line.strip_prefix('[')?.strip_suffix(']')
+}
+
+/// Reads `key: value` from the [TRANSACTION] block.
+pub fn transaction_field(content: &str, key: &str) -> Option<String> {
+    let prefix = format!("{}:", key);
+    let mut in_transaction = false;
+    for line in content.lines() {
+        if let Some(name) = section_header(line) {
+            in_transaction = name == "TRANSACTION";
+        } else if in_transaction && let Some(value) = line.strip_prefix(&prefix) {
+            return Some(value.trim().to_string());
+        }
+    }
+    None
+}
+
+/// Replaces `key: value` inside the [TRANSACTION] block, appending the line
+/// at the end of the block when the key is not present yet.
+pub fn set_transaction_field(content: &str, key: &str, value: &str) -> String {
+    let prefix = format!("{}:", key);
+    let new_line = format!("{}: {}", key, value);
+    let mut out: Vec<String> = Vec::new();
+    let mut in_transaction = false;
+    let mut last_field = None;
+    let mut replaced = false;
+
+    for line in content.lines() {
+        if let Some(name) = section_header(line) {
+            in_transaction = name == "TRANSACTION";
+        } else if in_transaction && line.starts_with(&prefix) && !replaced {
+            out.push(new_line.clone());
+            replaced = true;
+            continue;
+        } else if in_transaction && !line.trim().is_empty() {
+            last_field = Some(out.len());
+        }
+        out.push(line.to_string());
+    }
+    if !replaced && let Some(idx) = last_field {
+        out.insert(idx + 1, new_line);
+    }
+
+    let mut result = out.join("\n");
+    if content.ends_with('\n') {
+        result.push('\n');
+    }
+    result
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Exam {
+    pub code: String,
+    pub date: String,
+    pub result: String,
+}
+
+/// Lines of a `[NAME]` section, up to the next section header.
+pub fn section_text(content: &str, name: &str) -> Option<String> {
+    let mut lines: Option<Vec<&str>> = None;
+    for line in content.lines() {
+        match section_header(line) {
+            Some(header) if header == name => lines = Some(Vec::new()),
+            Some(_) if lines.is_some() => break,
+            _ => {
+                if let Some(lines) = lines.as_mut() {
+                    lines.push(line);
+                }
+            }
+        }
+    }
+    lines.map(|l| l.join("\n").trim().to_string())
+}
+
+/// Sections other than the header blocks and [CONTENT], as `(name, text)`
+/// in file order.
+pub fn content_sections(content: &str) -> Vec<(String, String)> {
+    const HEADERS: &[&str] = &["PATIENT", "DOCTOR", "TRANSACTION", "CONTRACT_CONDITIONS", "CONTENT"];
+    let mut out: Vec<(String, Vec<&str>)> = Vec::new();
+    let mut collecting = false;
+    for line in content.lines() {
+        if let Some(name) = section_header(line) {
+            collecting = !HEADERS.contains(&name);
+            if collecting {
+                out.push((name.to_string(), Vec::new()));
+            }
+        } else if collecting && let Some((_, lines)) = out.last_mut() {
+            lines.push(line);
+        }
+    }
+    out.into_iter().map(|(name, lines)| (name, lines.join("\n").trim().to_string())).collect()
+}
+
+/// Exam results reported as `!EX @RM_CRANIO[07/2025]: NORMAL;` (or `!RX`).
+pub fn parse_exams(content: &str) -> Vec<Exam> {
+    let re = Regex::new(r"!(?:EX|RX)\s*@([^\[;:]+?)\s*\[([^\]]*)\]\s*:\s*([^;\n]*)").unwrap();
+    re.captures_iter(content)
+        .map(|cap| Exam {
+            code: cap[1].trim().to_string(),
+            date: cap[2].trim().to_string(),
+            result: cap[3].trim().to_string(),
+        })
+        .collect()
+}
+
+pub fn calculate_age(birth_date: &str) -> String {
+    let birth = chrono::NaiveDate::parse_from_str(birth_date, "%Y-%m-%d").unwrap_or(chrono::Utc::now().date_naive());
+    let today = chrono::Utc::now().date_naive();
+    let age = (today - birth).num_days() / 365;
+    age.to_string()
+}
+
+pub fn parse_patient_from_med(content: &str) -> Option<Patient> {
+    let patient_re = Regex::new(r"(?s)\[PATIENT\]\s*(.*?)\[DOCTOR\]").unwrap();
+    let patient_section = patient_re.captures(content)?.get(1)?.as_str();
+
+    let cpf = Cpf::normalize(&extract_field(patient_section, "CPF:")?);
+    let nome = extract_field(patient_section, "Nome:")?;
+    let idade_str = extract_field(patient_section, "Idade:")?;
+    let idade_num: i32 = idade_str.parse().ok()?;
+    let today = chrono::Utc::now().date_naive();
+    let birth = today - chrono::Duration::days(idade_num as i64 * 365);
+    let birth_date = birth.format("%Y-%m-%d").to_string();
+
+    Some(Patient { cpf, nome, birth_date })
+}
+
+/// Reads every .med file of the medfiles tree as `(path, content)`.
+pub fn load_med_files() -> Vec<(std::path::PathBuf, String)> {
+    WalkDir::new(medfiles_dir())
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().and_then(|s| s.to_str()) == Some("med"))
+        .filter_map(|e| match storage::read_med(e.path()) {
+            Ok(content) => Some((e.into_path(), content)),
+            Err(err) => {
+                eprintln!("Falha ao ler {}: {}", e.path().display(), err);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Every consultation of the medfiles tree, filed under the kept CPF when
+/// the patient's record was merged into another.
+pub fn load_consultations() -> Vec<Consultation> {
+    let aliases = patients::aliases(&db::open());
+    let mut consultations = Vec::new();
+    for (path, content) in load_med_files() {
+        if let (Some(mut cons), Some(file_name)) = (parse_med_file(&content), path.file_name().and_then(|n| n.to_str())) {
+            cons.filename = file_name.to_string();
+            if let Some(kept) = aliases.get(&cons.patient.cpf) {
+                cons.patient.cpf = kept.clone();
+            }
+            consultations.push(cons);
+        }
+    }
+    consultations
+}
+
+/// Resolves a bare file name to its path inside the medfiles tree.
+pub fn find_med_file(filename: &str) -> Option<std::path::PathBuf> {
+    if filename.contains('/') || filename.contains("..") {
+        return None;
+    }
+    WalkDir::new(medfiles_dir())
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .find(|e| e.file_name().to_str() == Some(filename))
+        .map(|e| e.into_path())
+}
+
+pub fn parse_conduta_commands(conduta: &str) -> Vec<(String, String, String)> {
+    let re_bracket = Regex::new(r"!(\w+)\s*\[([^\]]+)\](?:\s*\[([^\]]+)\])?").unwrap();
+    let re_plain = Regex::new(r"!(\w+)\s*([^;\s]+)(?:\s*(.+))?").unwrap();
+    let mut commands = Vec::new();
+    // Split by ; to get individual commands
+    for part in conduta.split(';') {
+        let part = part.trim();
+        if part.starts_with('!') {
+            let (action, med, dosage) = if let Some(cap) = re_bracket.captures(part) {
+                (cap.get(1).unwrap().as_str().to_uppercase(),
+                 cap.get(2).unwrap().as_str().to_string(),
+                 cap.get(3).map_or("", |m| m.as_str()).to_string())
+            } else if let Some(cap) = re_plain.captures(part) {
+                (cap.get(1).unwrap().as_str().to_uppercase(),
+                 cap.get(2).unwrap().as_str().to_string(),
+                 cap.get(3).map_or("", |m| m.as_str()).to_string())
+            } else {
+                continue;
+            };
+            commands.push((action, med, dosage));
+        }
+    }
+    commands
+}
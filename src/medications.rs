@@ -0,0 +1,126 @@
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::medfile::{self, Consultation, Patient};
+use crate::{patients, storage};
+
+/// Active medications of a patient, drug name to dosage.
+pub type MedicationList = BTreeMap<String, String>;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Adjusted,
+    Suspended,
+    Kept,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MedicationChange {
+    pub kind: ChangeKind,
+    pub name: String,
+    pub dosage: String,
+    /// Dosage in use before the consultation, if the drug was active.
+    pub previous_dosage: Option<String>,
+}
+
+impl MedicationChange {
+    /// One-line summary, e.g. `~ LOSARTAN 50MG (antes: 25MG)`.
+    pub fn describe(&self) -> String {
+        let line = match self.kind {
+            ChangeKind::Added => format!("+ {} {}", self.name, self.dosage),
+            ChangeKind::Adjusted => format!("~ {} {}", self.name, self.dosage),
+            ChangeKind::Suspended => format!("- {}", self.name),
+            ChangeKind::Kept => format!("= {} {}", self.name, self.dosage),
+        };
+        match (&self.kind, &self.previous_dosage) {
+            (ChangeKind::Adjusted, Some(previous)) => format!("{} (antes: {})", line.trim_end(), previous),
+            _ => line.trim_end().to_string(),
+        }
+    }
+}
+
+/// Applies the !PRESCREVO/!AJUSTO/!SUSPENDO/!MANTENHO commands of a [CONDUTA]
+/// to `meds`, returning what changed.
+pub fn apply_conduta(meds: &mut MedicationList, conduta: &str) -> Vec<MedicationChange> {
+    let mut changes = Vec::new();
+    for (action, med, dosage) in medfile::parse_conduta_commands(conduta) {
+        let previous = meds.get(&med).cloned();
+        let kind = match action.as_str() {
+            "PRESCREVO" | "AJUSTO" => {
+                meds.insert(med.clone(), dosage.clone());
+                if previous.is_some() { ChangeKind::Adjusted } else { ChangeKind::Added }
+            }
+            "SUSPENDO" => {
+                meds.remove(&med);
+                ChangeKind::Suspended
+            }
+            "MANTENHO" => {
+                // Keep if already present, or add if not (assuming previous prescription)
+                meds.entry(med.clone()).or_insert(dosage.clone());
+                ChangeKind::Kept
+            }
+            _ => continue,
+        };
+        changes.push(MedicationChange { kind, name: med, dosage, previous_dosage: previous });
+    }
+    changes
+}
+
+/// Replays a patient's consultations in order to get the medications in use.
+pub fn current(consultations: &[Consultation]) -> MedicationList {
+    let mut sorted = consultations.to_vec();
+    medfile::sort_chronologically(&mut sorted);
+    let mut meds = MedicationList::new();
+    for c in &sorted {
+        apply_conduta(&mut meds, &c.conduta);
+    }
+    meds
+}
+
+/// The patient's rows in `patient_medications`, decrypted, as
+/// `(name, dosage, start_date)`.
+pub fn stored(conn: &Connection, cpf: &str) -> Result<Vec<(String, String, String)>, String> {
+    let rows: Vec<(String, String, String)> = conn.prepare(
+        "SELECT COALESCE(pm.drug, m.name), pm.dosage, pm.start_date FROM patient_medications pm
+         LEFT JOIN medications m ON pm.med_id = m.id WHERE pm.cpf = ?1 AND pm.status = 'active'").unwrap()
+        .query_map([cpf], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap()
+        .filter_map(|r| r.ok())
+        .collect();
+    rows.iter()
+        .map(|(name, dosage, start_date)| Ok((storage::open_field(name)?, storage::open_field(dosage)?, storage::open_field(start_date)?)))
+        .collect()
+}
+
+/// Rebuilds the `patient_medications` rows of every patient in
+/// `consultations`, registering patients and drugs not yet in the database.
+pub fn sync(conn: &Connection, consultations: &[Consultation]) -> Result<(), String> {
+    let mut patients_map: HashMap<String, (Patient, Vec<Consultation>)> = HashMap::new();
+
+    for cons in consultations {
+        patients_map.entry(cons.patient.cpf.clone()).or_insert_with(|| (cons.patient.clone(), Vec::new())).1.push(cons.clone());
+    }
+
+    let encrypted = storage::enabled();
+    for (cpf, (patient, mut cons)) in patients_map {
+        medfile::sort_chronologically(&mut cons);
+
+        conn.execute("INSERT OR IGNORE INTO patients (cpf, nome, birth_date, birth_date_source) VALUES (?1, ?2, ?3, ?4)",
+                     [&cpf, &storage::seal_field(&patient.nome)?, &storage::seal_field(&patient.birth_date)?, patients::FROM_MEDFILE]).unwrap();
+
+        // Clear old
+        conn.execute("DELETE FROM patient_medications WHERE cpf = ?1", [&cpf]).unwrap();
+
+        // Insert current
+        for (med, dosage) in current(&cons) {
+            conn.execute("INSERT OR IGNORE INTO medications (name) VALUES (?1)", [&med]).unwrap();
+            let med_id = conn.query_row("SELECT id FROM medications WHERE name = ?1", [&med], |row| row.get::<_, i64>(0)).unwrap();
+            let (med_id, drug) = if encrypted { (None, Some(storage::seal_field(&med)?)) } else { (Some(med_id), None) };
+            conn.execute("INSERT INTO patient_medications (cpf, med_id, drug, status, dosage, start_date) VALUES (?1, ?2, ?3, 'active', ?4, ?5)",
+                         (&cpf, &med_id, &drug, &storage::seal_field(&dosage)?, &storage::seal_field(&cons.first().unwrap().timestamp)?)).unwrap();
+        }
+    }
+    Ok(())
+}
@@ -1,20 +1,47 @@
 use clap::{Args, Parser, Subcommand};
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::terminal::{self, disable_raw_mode, enable_raw_mode, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use mymed::consult;
+use mymed::cpf::Cpf;
+use mymed::crm::Crm;
+use mymed::medfile::{self, Patient};
+use mymed::cli::{self, confirm, fail, report, wrap};
+use mymed::{db, duplicates, patients};
 use rusqlite::Connection;
-use crossterm::event::{read, Event, KeyCode, KeyEvent};
-use crossterm::terminal::{enable_raw_mode, disable_raw_mode};
+use std::collections::HashMap;
 use std::io::{stdout, Write};
+use std::process::Command;
 
 #[derive(Parser)]
 #[command(name = "mymed_patient_manager")]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Machine-readable output.
+    #[arg(long, global = true)]
+    json: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     Add(AddArgs),
-    List,
+    /// Browses the patients: typing filters the list, Enter opens the
+    /// viewer and Ctrl-E a new consultation in the editor.
+    List(ListArgs),
+    /// Changes the name and/or birth date of a registered patient.
+    Update(UpdateArgs),
+    /// Removes a patient without consultations from the registry.
+    Delete(DeleteArgs),
+    /// Finds patients by part of the name (ignoring accents) or of the CPF.
+    Search(SearchArgs),
+    /// Combines two records of the same person, keeping `--into`.
+    Merge(MergeArgs),
+    /// Lists pairs of patients that may be the same person; `--review`
+    /// asks about each one and merges the confirmed pairs.
+    Duplicates(DuplicatesArgs),
 }
 
 #[derive(Args)]
@@ -27,81 +54,480 @@ struct AddArgs {
     birth_date: String,
 }
 
+#[derive(Args)]
+struct ListArgs {
+    /// Doctor for the consultations opened with Ctrl-E; defaults to `MYMED_CRM`.
+    #[arg(long)]
+    crm: Option<Crm>,
+}
+
+#[derive(Args)]
+struct UpdateArgs {
+    #[arg(long)]
+    cpf: Cpf,
+    #[arg(long)]
+    nome: Option<String>,
+    /// DD/MM/AAAA or AAAA-MM-DD.
+    #[arg(long)]
+    birth_date: Option<String>,
+}
+
+#[derive(Args)]
+struct DeleteArgs {
+    #[arg(long)]
+    cpf: Cpf,
+    #[arg(long)]
+    yes: bool,
+}
+
+#[derive(Args)]
+struct SearchArgs {
+    term: String,
+}
+
+#[derive(Args)]
+struct MergeArgs {
+    /// Duplicate record, which becomes an alias.
+    #[arg(long)]
+    from: Cpf,
+    /// Record that is kept.
+    #[arg(long)]
+    into: Cpf,
+    #[arg(long)]
+    yes: bool,
+}
+
+#[derive(Args)]
+struct DuplicatesArgs {
+    /// Lowest score reported, from 0 to 1.
+    #[arg(long, default_value_t = duplicates::MIN_SCORE)]
+    min_score: f64,
+    #[arg(long)]
+    review: bool,
+}
+
+/// Reports field validation errors and exits with status 1.
+fn invalid(json: bool, errors: patients::FieldErrors) -> ! {
+    if json {
+        println!("{}", serde_json::json!({ "error": "Dados inválidos", "fields": errors }));
+    } else {
+        let message: Vec<String> = errors.iter().map(|(field, e)| format!("{}: {}", field, e)).collect();
+        eprintln!("{}", message.join("\n"));
+    }
+    std::process::exit(1);
+}
+
+fn describe(p: &Patient) -> String {
+    format!("{} - {} - {}", p.nome, p.cpf, p.birth_date)
+}
+
+fn describe_record(r: &duplicates::Record) -> String {
+    format!("{} - {}, {} consulta(s)", describe(&r.patient), if r.registered { "cadastrado" } else { "só nos .med" }, r.consultations)
+}
+
+/// Goes through the candidate pairs asking which record to keep. Merged CPFs
+/// drop out of the pairs still to come; pairs of different people are
+/// remembered and not shown again.
+fn review_duplicates(conn: &Connection, candidates: Vec<duplicates::Candidate>) {
+    let total = candidates.len();
+    let mut merged: Vec<String> = Vec::new();
+    let (mut merges, mut dismissals) = (0, 0);
+    for (i, candidate) in candidates.iter().enumerate() {
+        if merged.contains(&candidate.a.patient.cpf) || merged.contains(&candidate.b.patient.cpf) {
+            continue;
+        }
+        let (suggested, _) = candidate.suggestion();
+        let records = [&candidate.a, &candidate.b];
+        println!("\n[{}/{}] semelhança {:.2}: {}", i + 1, total, candidate.score, candidate.reasons.join(", "));
+        for (n, record) in records.iter().enumerate() {
+            let mark = if record.patient.cpf == suggested.patient.cpf { " (sugerido manter)" } else { "" };
+            println!("  {}) {}{}", n + 1, describe_record(record), mark);
+        }
+        eprintln!("Manter 1 ou 2, n = pessoas diferentes, Enter = pular, q = sair:");
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer).unwrap() == 0 {
+            break;
+        }
+        let (kept, from) = match answer.trim().to_lowercase().as_str() {
+            "1" => (records[0], records[1]),
+            "2" => (records[1], records[0]),
+            "n" => {
+                duplicates::dismiss(conn, &candidate.a.patient.cpf, &candidate.b.patient.cpf);
+                dismissals += 1;
+                continue;
+            }
+            "q" => break,
+            _ => continue,
+        };
+        match patients::merge(conn, &from.patient.cpf, &kept.patient.cpf) {
+            Ok(merge) => {
+                cli::audit("merge_patient", Some(&merge.into), &format!("mymed_patient_manager duplicates --review --from {}", merge.from));
+                println!("{} unificado a {}: {} consulta(s), {} autorização(ões) de acesso transferida(s).",
+                         merge.from, merge.into, merge.consultations, merge.access_grants);
+                merged.push(merge.from);
+                merges += 1;
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    println!("{} unificação(ões), {} par(es) marcado(s) como pessoas diferentes.", merges, dismissals);
+}
+
+/// Width of the patient list; the detail pane takes the rest of the screen.
+const LIST_WIDTH: usize = 44;
+const HELP: &str = "Digite para filtrar  ↑/↓ PgUp/PgDn mover  Enter visualizar  Ctrl-E nova consulta  Esc sair";
+
+/// Another mymed binary, installed next to this one.
+fn sibling(name: &str) -> std::path::PathBuf {
+    std::env::current_exe().ok().and_then(|exe| exe.parent().map(|dir| dir.join(name))).unwrap_or_else(|| name.into())
+}
+
+struct Browser {
+    conn: Connection,
+    patients: Vec<Patient>,
+    crm: Option<String>,
+    filter: String,
+    /// Indexes into `patients` that match the filter.
+    shown: Vec<usize>,
+    selected: usize,
+    top: usize,
+    status: String,
+    /// Detail pane lines per CPF, as `(text, title)`.
+    details: HashMap<String, Vec<(String, bool)>>,
+}
+
+impl Browser {
+    fn new(conn: Connection, mut patients: Vec<Patient>, crm: Option<String>) -> Browser {
+        patients.sort_by_key(|p| medfile::fold(&p.nome));
+        let mut browser = Browser {
+            conn,
+            patients,
+            crm,
+            filter: String::new(),
+            shown: Vec::new(),
+            selected: 0,
+            top: 0,
+            status: String::new(),
+            details: HashMap::new(),
+        };
+        browser.refilter();
+        browser
+    }
+
+    fn refilter(&mut self) {
+        let current = self.current().map(|p| p.cpf.clone());
+        self.shown = (0..self.patients.len()).filter(|&i| patients::matches(&self.patients[i], &self.filter)).collect();
+        self.selected = current.and_then(|cpf| self.shown.iter().position(|&i| self.patients[i].cpf == cpf)).unwrap_or(0);
+        self.status = format!("{} de {} pacientes", self.shown.len(), self.patients.len());
+    }
+
+    fn current(&self) -> Option<&Patient> {
+        self.shown.get(self.selected).map(|&i| &self.patients[i])
+    }
+
+    fn detail(&mut self, width: usize) -> Vec<(String, bool)> {
+        let Some(patient) = self.current().cloned() else {
+            return vec![("Nenhum paciente corresponde ao filtro.".to_string(), false)];
+        };
+        if let Some(lines) = self.details.get(&patient.cpf) {
+            return lines.clone();
+        }
+        let mut out = vec![
+            (patient.nome.clone(), true),
+            (format!("CPF: {}", patient.cpf), false),
+            (format!("Nascimento: {} ({} anos)", patient.birth_date, medfile::calculate_age(&patient.birth_date)), false),
+            (String::new(), false),
+            ("MEDICAÇÕES EM USO".to_string(), true),
+        ];
+        let chart = consult::chart(&self.conn, &patient.cpf);
+        match &chart {
+            Some(chart) if !chart.medications.is_empty() => {
+                for (name, dosage) in &chart.medications {
+                    out.extend(wrap(&format!("- {} {}", name, dosage), width).into_iter().map(|l| (l, false)));
+                }
+            }
+            _ => out.push(("(nenhuma)".to_string(), false)),
+        }
+        out.push((String::new(), false));
+        out.push(("ÚLTIMA CONSULTA".to_string(), true));
+        match chart.as_ref().and_then(|c| c.last()) {
+            Some(last) => {
+                out.push((format!("{} - {} consulta(s)", last.timestamp.get(..10).unwrap_or(&last.timestamp), chart.as_ref().map_or(0, |c| c.history.len())), false));
+                out.extend(wrap(&format!("{} ({}) - CRM {}", last.doctor.nome, last.doctor.especialidade, last.doctor.crm), width).into_iter().map(|l| (l, false)));
+                out.extend(wrap(&format!("Hipótese: {}", last.hipotese_diagnostica), width).into_iter().map(|l| (l, false)));
+            }
+            None => out.push(("(nenhuma)".to_string(), false)),
+        }
+        out.push((String::new(), false));
+        out.push(("PEDIDOS PENDENTES".to_string(), true));
+        let orders = chart.as_ref().map(consult::pending_orders).unwrap_or_default();
+        if orders.is_empty() {
+            out.push(("(nenhum)".to_string(), false));
+        }
+        for (date, order) in orders {
+            out.extend(wrap(&format!("- {} {}", date, order), width).into_iter().map(|l| (l, false)));
+        }
+        self.details.insert(patient.cpf, out.clone());
+        out
+    }
+
+    fn draw(&mut self) -> std::io::Result<()> {
+        let (cols, rows) = terminal::size()?;
+        let (cols, rows) = (cols as usize, rows as usize);
+        let list_width = if cols > LIST_WIDTH + 30 { LIST_WIDTH } else { cols };
+        let height = rows.saturating_sub(3).max(1);
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + height {
+            self.top = self.selected + 1 - height;
+        }
+
+        let mut out = stdout();
+        queue!(out, Clear(ClearType::All), MoveTo(0, 0), SetAttribute(Attribute::Bold), Print("Buscar: "),
+               SetAttribute(Attribute::Reset), Print(&self.filter))?;
+        for (y, &i) in self.shown.iter().skip(self.top).take(height).enumerate() {
+            let p = &self.patients[i];
+            let line: String = format!(" {:<w$} {}", p.nome, p.cpf, w = list_width.saturating_sub(17)).chars().take(list_width).collect();
+            queue!(out, MoveTo(0, y as u16 + 1))?;
+            if self.top + y == self.selected {
+                queue!(out, SetAttribute(Attribute::Reverse), Print(format!("{:<w$}", line, w = list_width)), SetAttribute(Attribute::Reset))?;
+            } else {
+                queue!(out, Print(line))?;
+            }
+        }
+        if list_width < cols {
+            let x = list_width + 2;
+            let width = cols - x - 1;
+            for y in 0..height {
+                queue!(out, MoveTo(list_width as u16, y as u16 + 1), SetForegroundColor(Color::DarkGrey), Print("│"), ResetColor)?;
+            }
+            for (y, (text, title)) in self.detail(width).into_iter().take(height).enumerate() {
+                let text: String = text.chars().take(width).collect();
+                queue!(out, MoveTo(x as u16, y as u16 + 1))?;
+                if title {
+                    queue!(out, SetAttribute(Attribute::Bold), Print(text), SetAttribute(Attribute::Reset))?;
+                } else {
+                    queue!(out, Print(text))?;
+                }
+            }
+        }
+        let status: String = self.status.chars().take(cols).collect();
+        let help: String = HELP.chars().take(cols).collect();
+        queue!(out, MoveTo(0, height as u16 + 1), SetAttribute(Attribute::Reverse), Print(format!("{:<width$}", status, width = cols)),
+               SetAttribute(Attribute::Reset), MoveTo(0, height as u16 + 2), SetForegroundColor(Color::DarkGrey), Print(help), ResetColor)?;
+        out.flush()
+    }
+
+    /// Leaves the screen to run another mymed program on the selected
+    /// patient, then reloads, since it may have written a consultation.
+    fn open(&mut self, program: &str, args: &[&str], wait: bool) -> std::io::Result<()> {
+        execute!(stdout(), Show, LeaveAlternateScreen)?;
+        disable_raw_mode()?;
+        let status = Command::new(sibling(program)).args(args).status();
+        match &status {
+            Ok(s) if !s.success() => eprintln!("{} terminou com {}.", program, s),
+            Err(e) => eprintln!("Falha ao executar {}: {}", program, e),
+            _ => {}
+        }
+        if wait || !matches!(status, Ok(s) if s.success()) {
+            eprintln!("\nPressione Enter para voltar à lista...");
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line)?;
+        }
+        enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen, Hide)?;
+        self.details.clear();
+        let mut all = patients::search(&self.conn, "");
+        all.sort_by_key(|p| medfile::fold(&p.nome));
+        self.patients = all;
+        self.refilter();
+        Ok(())
+    }
+
+    /// Handles one key; returns true when the list should close.
+    fn key(&mut self, key: KeyEvent) -> std::io::Result<bool> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let page = terminal::size()?.1.saturating_sub(3).max(1) as usize;
+        let last = self.shown.len().saturating_sub(1);
+        match key.code {
+            KeyCode::Esc if !self.filter.is_empty() => {
+                self.filter.clear();
+                self.refilter();
+            }
+            KeyCode::Esc => return Ok(true),
+            KeyCode::Char('c' | 'q') if ctrl => return Ok(true),
+            KeyCode::Char('e') if ctrl => {
+                let Some(cpf) = self.current().map(|p| p.cpf.clone()) else { return Ok(false) };
+                match self.crm.clone() {
+                    Some(crm) => self.open("mymed_editor", &["--patient-cpf", &cpf, "--doctor-crm", &crm], true)?,
+                    None => self.status = "Informe --crm ou MYMED_CRM para abrir o editor.".to_string(),
+                }
+            }
+            KeyCode::Enter => {
+                let Some(cpf) = self.current().map(|p| p.cpf.clone()) else { return Ok(false) };
+                self.open("mymed_viewer", &["patient", "--cpf", &cpf], true)?;
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.filter.push(c);
+                self.refilter();
+            }
+            KeyCode::Backspace => {
+                self.filter.pop();
+                self.refilter();
+            }
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => self.selected = (self.selected + 1).min(last),
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(page),
+            KeyCode::PageDown => self.selected = (self.selected + page).min(last),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = last,
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    fn run(&mut self) -> std::io::Result<()> {
+        enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen, Hide)?;
+        let result = (|| loop {
+            self.draw()?;
+            if let Event::Key(key) = read()?
+                && key.kind == KeyEventKind::Press
+                && self.key(key)?
+            {
+                return Ok(());
+            }
+        })();
+        execute!(stdout(), Show, LeaveAlternateScreen)?;
+        disable_raw_mode()?;
+        result
+    }
+}
+
 fn main() {
     let cli = Cli::parse();
-    let conn = Connection::open("medications.db").unwrap();
+    let json = cli.json;
+    db::setup_db();
+    let conn = db::open();
     match cli.command {
         Commands::Add(args) => {
-            // check if cpf exists
-            let count: i64 = conn.query_row("SELECT COUNT(*) FROM patients WHERE cpf = ?1", [&args.cpf], |row| row.get(0)).unwrap_or(0);
-            if count > 0 {
-                eprintln!("CPF já cadastrado.");
-                std::process::exit(1);
+            let patient = match patients::validate(&args.cpf, &args.nome, &args.birth_date) {
+                Ok(patient) => patient,
+                Err(errors) => invalid(json, errors),
+            };
+            if let Err(e) = patients::insert(&conn, &patient, patients::FROM_REGISTRY) {
+                fail(json, e);
             }
-            conn.execute("INSERT INTO patients (cpf, nome, birth_date) VALUES (?1, ?2, ?3)",
-                         [&args.cpf, &args.nome, &args.birth_date]).unwrap();
-            println!("Paciente adicionado.");
-        }
-        Commands::List => {
-            let mut stmt = conn.prepare("SELECT nome, cpf, birth_date FROM patients ORDER BY nome").unwrap();
-            let mut patients: Vec<(String, String, String)> = vec![];
-            let patient_iter = stmt.query_map([], |row| {
-                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
-            }).unwrap();
-            for patient in patient_iter {
-                patients.push(patient.unwrap());
-            }
-            if patients.is_empty() {
-                println!("No patients found.");
+            cli::audit("create_patient", Some(&patient.cpf), "mymed_patient_manager add");
+            report(json, serde_json::json!(patient), "Paciente adicionado.");
+        }
+        Commands::List(args) => {
+            let mut all = patients::search(&conn, "");
+            if json {
+                all.sort_by(|a, b| a.nome.cmp(&b.nome));
+                report(json, serde_json::json!(all), "");
+                return;
+            }
+            if all.is_empty() {
+                println!("Nenhum paciente encontrado.");
                 return;
             }
-            enable_raw_mode().unwrap();
-            let mut selected = 0;
-            loop {
-                // Clear screen and print list
-                print!("\x1B[2J\x1B[1;1H");
-                stdout().flush().unwrap();
-                for (i, (nome, cpf, _)) in patients.iter().enumerate() {
-                    if i == selected {
-                        println!("> {}. {} - {}", i + 1, nome, cpf);
-                    } else {
-                        println!("  {}. {} - {}", i + 1, nome, cpf);
-                    }
+            if !atty::is(atty::Stream::Stdout) || !atty::is(atty::Stream::Stdin) {
+                for p in &all {
+                    println!("{}", describe(p));
+                }
+                return;
+            }
+            let crm = args.crm.map(String::from).or_else(|| std::env::var("MYMED_CRM").ok().map(|c| Crm::normalize(&c))).filter(|c| !c.is_empty());
+            let mut browser = Browser::new(conn, all, crm);
+            if let Err(e) = browser.run() {
+                eprintln!("Erro no terminal: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Update(args) => {
+            let Some(current) = patients::get(&conn, &args.cpf) else {
+                fail(json, "Paciente não encontrado.");
+            };
+            if args.nome.is_none() && args.birth_date.is_none() {
+                fail(json, "Informe --nome e/ou --birth-date.");
+            }
+            let nome = args.nome.unwrap_or_else(|| current.nome.clone());
+            let birth_date = args.birth_date.unwrap_or_else(|| current.birth_date.clone());
+            let patient = match patients::validate_update(&current, &nome, &birth_date) {
+                Ok(patient) => patient,
+                Err(errors) => invalid(json, errors),
+            };
+            if let Err(e) = patients::update(&conn, &patient) {
+                fail(json, e);
+            }
+            cli::audit("update_patient", Some(&patient.cpf), "mymed_patient_manager update");
+            report(json, serde_json::json!(patient), format!("Paciente atualizado: {}", describe(&patient)));
+        }
+        Commands::Delete(args) => {
+            let Some(patient) = patients::get(&conn, &args.cpf) else {
+                fail(json, "Paciente não encontrado.");
+            };
+            let consultations = medfile::load_consultations().into_iter().filter(|c| c.patient.cpf == *args.cpf).count();
+            if consultations > 0 {
+                fail(json, format!("Paciente possui {} consulta(s) registrada(s); use `mymed patient erase` para eliminação \
+                                    ou `merge` se for um cadastro duplicado.", consultations));
+            }
+            if !confirm(json, args.yes, &format!("Remover {}?", describe(&patient))) {
+                fail(json, "Cancelado.");
+            }
+            patients::delete(&conn, &args.cpf);
+            cli::audit("delete_patient", Some(&args.cpf), "mymed_patient_manager delete");
+            report(json, serde_json::json!({ "deleted": args.cpf }), "Paciente removido.");
+        }
+        Commands::Search(args) => {
+            let found = patients::search(&conn, &args.term);
+            if json {
+                report(json, serde_json::json!(found), "");
+            } else if found.is_empty() {
+                println!("Nenhum paciente encontrado.");
+            } else {
+                for p in &found {
+                    println!("{}", describe(p));
+                }
+            }
+        }
+        Commands::Merge(args) => {
+            let name = |cpf: &str| patients::search(&conn, cpf).into_iter().find(|p| p.cpf == cpf).map(|p| p.nome).unwrap_or_default();
+            let question = format!("Unificar {} ({}) em {} ({})? As consultas de {} passarão a constar em {}.",
+                                   args.from, name(&args.from), args.into, name(&args.into), args.from, args.into);
+            if !confirm(json, args.yes, &question) {
+                fail(json, "Cancelado.");
+            }
+            let merge = match patients::merge(&conn, &args.from, &args.into) {
+                Ok(merge) => merge,
+                Err(e) => fail(json, e),
+            };
+            cli::audit("merge_patient", Some(&merge.into), &format!("mymed_patient_manager merge --from {}", merge.from));
+            report(json, serde_json::json!(merge), format!("{} unificado a {}: {} consulta(s), {} autorização(ões) de acesso transferida(s).",
+                                                          merge.from, merge.into, merge.consultations, merge.access_grants));
+        }
+        Commands::Duplicates(args) => {
+            let candidates = duplicates::find(&conn, args.min_score);
+            if args.review {
+                if json || !atty::is(atty::Stream::Stdin) {
+                    fail(json, "--review precisa de um terminal e não combina com --json.");
+                }
+                if candidates.is_empty() {
+                    println!("Nenhum possível duplicado.");
+                } else {
+                    review_duplicates(&conn, candidates);
                 }
-                println!("\nUse ↑/↓ to navigate, Enter to select, 'q' to quit.");
-                match read().unwrap() {
-                    Event::Key(KeyEvent { code: KeyCode::Up, .. }) => {
-                        if selected > 0 {
-                            selected -= 1;
-                        }
-                    }
-                    Event::Key(KeyEvent { code: KeyCode::Down, .. }) => {
-                        if selected < patients.len() - 1 {
-                            selected += 1;
-                        }
-                    }
-                    Event::Key(KeyEvent { code: KeyCode::Enter, .. }) => {
-                        let (nome, cpf, birth_date) = &patients[selected];
-                        print!("\x1B[2J\x1B[1;1H");
-                        stdout().flush().unwrap();
-                        println!("Patient Details:");
-                        println!("Name: {}", nome);
-                        println!("CPF: {}", cpf);
-                        println!("Birth Date: {}", birth_date);
-                        println!("\nPress Enter to return to list...");
-                        loop {
-                            if let Event::Key(KeyEvent { code: KeyCode::Enter, .. }) = read().unwrap() {
-                                break;
-                            }
-                        }
-                    }
-                    Event::Key(KeyEvent { code: KeyCode::Char('q'), .. }) => {
-                        break;
-                    }
-                    _ => {}
+            } else if json {
+                println!("{}", serde_json::to_string_pretty(&candidates).unwrap());
+            } else if candidates.is_empty() {
+                println!("Nenhum possível duplicado.");
+            } else {
+                for candidate in &candidates {
+                    println!("{:.2}  {} ~ {}  ({})", candidate.score, describe(&candidate.a.patient), describe(&candidate.b.patient),
+                             candidate.reasons.join(", "));
                 }
             }
-            disable_raw_mode().unwrap();
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,201 @@
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::cpf::Cpf;
+use crate::medfile::{self, Patient};
+use crate::{duplicates, medications, storage};
+
+/// Validation messages keyed by the offending field.
+pub type FieldErrors = BTreeMap<&'static str, String>;
+
+/// Accepts `DD/MM/AAAA` or `AAAA-MM-DD` and returns the stored `AAAA-MM-DD`.
+pub fn parse_birth_date(input: &str) -> Result<String, String> {
+    let input = input.trim();
+    let date = chrono::NaiveDate::parse_from_str(input, "%d/%m/%Y")
+        .or_else(|_| chrono::NaiveDate::parse_from_str(input, "%Y-%m-%d"))
+        .map_err(|_| "Data inválida. Use DD/MM/AAAA.".to_string())?;
+    if date > chrono::Utc::now().date_naive() {
+        return Err("Data de nascimento no futuro.".to_string());
+    }
+    if date < chrono::NaiveDate::from_ymd_opt(1900, 1, 1).unwrap() {
+        return Err("Data de nascimento anterior a 1900.".to_string());
+    }
+    Ok(date.format("%Y-%m-%d").to_string())
+}
+
+fn check_details(nome: &str, birth_date: &str, errors: &mut FieldErrors) -> String {
+    if nome.trim().is_empty() {
+        errors.insert("nome", "Nome obrigatório.".to_string());
+    }
+    parse_birth_date(birth_date).unwrap_or_else(|e| {
+        errors.insert("birth_date", e);
+        String::new()
+    })
+}
+
+/// Validates new name and birth date for an already registered patient.
+pub fn validate_update(current: &Patient, nome: &str, birth_date: &str) -> Result<Patient, FieldErrors> {
+    let mut errors = FieldErrors::new();
+    let birth_date = check_details(nome, birth_date, &mut errors);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(Patient { cpf: current.cpf.clone(), nome: nome.trim().to_string(), birth_date })
+}
+
+/// Validates a new patient's data, normalizing CPF and birth date.
+pub fn validate(cpf: &str, nome: &str, birth_date: &str) -> Result<Patient, FieldErrors> {
+    let mut errors = FieldErrors::new();
+    let cpf = match Cpf::parse(cpf) {
+        Ok(cpf) if cpf.is_valid() => cpf.into(),
+        Ok(_) => {
+            errors.insert("cpf", "CPF com dígitos verificadores inválidos.".to_string());
+            String::new()
+        }
+        Err(e) => {
+            errors.insert("cpf", e);
+            String::new()
+        }
+    };
+    let birth_date = check_details(nome, birth_date, &mut errors);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(Patient { cpf, nome: nome.trim().to_string(), birth_date })
+}
+
+fn patient_from_row(row: &rusqlite::Row) -> rusqlite::Result<Patient> {
+    let open = |i: usize| storage::open_field(&row.get::<_, String>(i)?)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(i, rusqlite::types::Type::Text, e.into()));
+    Ok(Patient { cpf: row.get(0)?, nome: open(1)?, birth_date: open(2)? })
+}
+
+pub fn get(conn: &Connection, cpf: &str) -> Option<Patient> {
+    conn.query_row("SELECT cpf, nome, birth_date FROM patients WHERE cpf = ?1", [cpf], patient_from_row).optional().unwrap()
+}
+
+pub fn all(conn: &Connection) -> Vec<Patient> {
+    let mut stmt = conn.prepare("SELECT cpf, nome, birth_date FROM patients").unwrap();
+    stmt.query_map([], patient_from_row).unwrap().filter_map(|p| p.ok()).collect()
+}
+
+/// `birth_date_source` of a birth date typed in by a person.
+pub const FROM_REGISTRY: &str = "cadastro";
+/// `birth_date_source` of a birth date estimated from the `Idade:` of a .med file.
+pub const FROM_MEDFILE: &str = "medfile";
+
+/// Registers the patient; `source` tells where the birth date came from.
+pub fn insert(conn: &Connection, patient: &Patient, source: &str) -> Result<(), String> {
+    if get(conn, &patient.cpf).is_some() {
+        return Err("CPF já cadastrado.".to_string());
+    }
+    conn.execute("INSERT INTO patients (cpf, nome, birth_date, birth_date_source) VALUES (?1, ?2, ?3, ?4)",
+                 [&patient.cpf, &storage::seal_field(&patient.nome)?, &storage::seal_field(&patient.birth_date)?, source]).unwrap();
+    Ok(())
+}
+
+/// Where each registered birth date came from, `None` for rows written before
+/// the source was recorded.
+pub fn birth_date_sources(conn: &Connection) -> HashMap<String, Option<String>> {
+    let mut stmt = conn.prepare("SELECT cpf, birth_date_source FROM patients").unwrap();
+    stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().filter_map(|s| s.ok()).collect()
+}
+
+/// Corrects the patient's name and birth date, which then count as registered.
+pub fn update(conn: &Connection, patient: &Patient) -> Result<(), String> {
+    conn.execute(&format!("UPDATE patients SET nome = ?2, birth_date = ?3, birth_date_source = '{}' WHERE cpf = ?1", FROM_REGISTRY),
+                 [&patient.cpf, &storage::seal_field(&patient.nome)?, &storage::seal_field(&patient.birth_date)?]).unwrap();
+    Ok(())
+}
+
+/// Removes the patient row and its medication list; returns whether it existed.
+pub fn delete(conn: &Connection, cpf: &str) -> bool {
+    conn.execute("DELETE FROM patient_medications WHERE cpf = ?1", [cpf]).unwrap();
+    conn.execute("DELETE FROM patients WHERE cpf = ?1", [cpf]).unwrap() > 0
+}
+
+/// Whether the patient's name contains `term`, ignoring case and accents, or,
+/// for a term of digits and CPF punctuation only, their CPF contains it.
+pub fn matches(patient: &Patient, term: &str) -> bool {
+    let term = medfile::fold(term.trim());
+    let digits: String = term.chars().filter(|c| c.is_ascii_digit()).collect();
+    let cpf_like = !digits.is_empty() && term.chars().all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | ' '));
+    medfile::fold(&patient.nome).contains(&term)
+        || (cpf_like && patient.cpf.chars().filter(|c| c.is_ascii_digit()).collect::<String>().contains(&digits))
+}
+
+/// Registered and consultation-only patients that `matches` the term.
+pub fn search(conn: &Connection, term: &str) -> Vec<Patient> {
+    let mut found: BTreeMap<String, Patient> = medfile::load_consultations().into_iter()
+        .map(|c| (c.patient.cpf.clone(), c.patient))
+        .collect();
+    found.extend(all(conn).into_iter().map(|p| (p.cpf.clone(), p)));
+    let mut found: Vec<Patient> = found.into_values()
+        .filter(|p| matches(p, term))
+        .collect();
+    found.sort_by_key(|p| medfile::fold(&p.nome));
+    found
+}
+
+/// CPFs merged into another record, mapped to the CPF that was kept.
+pub fn aliases(conn: &Connection) -> HashMap<String, String> {
+    let Ok(mut stmt) = conn.prepare("SELECT cpf, merged_into FROM patient_aliases") else {
+        return HashMap::new();
+    };
+    stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().filter_map(|a| a.ok()).collect()
+}
+
+/// CPFs that were merged into `cpf`.
+pub fn merged_from(conn: &Connection, cpf: &str) -> Vec<String> {
+    aliases(conn).into_iter().filter(|(_, kept)| kept == cpf).map(|(merged, _)| merged).collect()
+}
+
+#[derive(Serialize)]
+pub struct Merge {
+    pub from: String,
+    pub into: String,
+    /// Consultations now listed under `into`.
+    pub consultations: usize,
+    pub access_grants: usize,
+}
+
+/// Folds the record of `from` into `into`, for the same person filed twice.
+/// The .med files are signed and sealed as written, so they keep the old CPF:
+/// `from` becomes an alias that `load_consultations` resolves to `into`. The
+/// registry row, medication list and access grants move to `into`; the ledger
+/// chain and audit log are append-only and stay under `from`.
+pub fn merge(conn: &Connection, from: &str, into: &str) -> Result<Merge, String> {
+    if from == into {
+        return Err("Os CPFs são iguais.".to_string());
+    }
+    let aliases = aliases(conn);
+    for cpf in [from, into] {
+        if let Some(kept) = aliases.get(cpf) {
+            return Err(format!("{} já foi unificado a {}.", cpf, kept));
+        }
+    }
+    let consultations = medfile::load_consultations();
+    let known = |cpf: &str| get(conn, cpf).is_some() || consultations.iter().any(|c| c.patient.cpf == cpf);
+    for cpf in [from, into] {
+        if !known(cpf) {
+            return Err(format!("Paciente {} não encontrado.", cpf));
+        }
+    }
+
+    if get(conn, into).is_none() && let Some(source) = get(conn, from) {
+        insert(conn, &Patient { cpf: into.to_string(), ..source }, FROM_REGISTRY)?;
+        conn.execute("UPDATE patients SET birth_date_source = (SELECT birth_date_source FROM patients WHERE cpf = ?1) WHERE cpf = ?2",
+                     [from, into]).unwrap();
+    }
+    let access_grants = conn.execute("UPDATE access_grants SET cpf = ?2 WHERE cpf = ?1", [from, into]).unwrap();
+    delete(conn, from);
+    duplicates::forget(conn, from);
+    conn.execute("UPDATE patient_aliases SET merged_into = ?2 WHERE merged_into = ?1", [from, into]).unwrap();
+    conn.execute("INSERT OR REPLACE INTO patient_aliases (cpf, merged_into, merged_at) VALUES (?1, ?2, ?3)",
+                 [from, into, &chrono::Utc::now().to_rfc3339()]).unwrap();
+
+    let history: Vec<_> = medfile::load_consultations().into_iter().filter(|c| c.patient.cpf == into).collect();
+    medications::sync(conn, &history)?;
+    Ok(Merge { from: from.to_string(), into: into.to_string(), consultations: history.len(), access_grants })
+}
@@ -0,0 +1,198 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::crm::Crm;
+use crate::medfile;
+
+/// Directory holding the doctors' private keys, one `<CRM>.key` file each.
+pub const KEYS_DIR: &str = "keys";
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureStatus {
+    Valid,
+    Unsigned,
+    HashMismatch,
+    UnknownKey,
+    Invalid,
+}
+
+impl SignatureStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SignatureStatus::Valid => "assinatura válida",
+            SignatureStatus::Unsigned => "não assinado",
+            SignatureStatus::HashMismatch => "conteúdo alterado após a assinatura",
+            SignatureStatus::UnknownKey => "chave do médico não cadastrada",
+            SignatureStatus::Invalid => "assinatura inválida",
+        }
+    }
+}
+
+/// The text that gets hashed: the whole file with normalized line endings and
+/// trailing whitespace, minus the `Hash:`/`Signature:` lines that carry the
+/// result of signing it.
+pub fn canonical_content(content: &str) -> String {
+    let mut lines = Vec::new();
+    let mut in_transaction = false;
+    for line in content.lines() {
+        let line = line.trim_end();
+        if let Some(name) = medfile::section_header(line) {
+            in_transaction = name == "TRANSACTION";
+        } else if in_transaction && (line.starts_with("Hash:") || line.starts_with("Signature:")) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+pub fn content_hash(content: &str) -> String {
+    hex::encode(Sha256::digest(canonical_content(content).as_bytes()))
+}
+
+fn key_path(crm: &str) -> PathBuf {
+    Path::new(KEYS_DIR).join(format!("{}.key", crm))
+}
+
+pub fn generate_key(conn: &Connection, crm: &str) -> Result<String, String> {
+    if public_key(conn, crm).is_some() {
+        return Err(format!("Já existe chave para o CRM {}.", crm));
+    }
+    let signing_key = SigningKey::generate(&mut OsRng);
+    let public_hex = hex::encode(signing_key.verifying_key().to_bytes());
+
+    std::fs::create_dir_all(KEYS_DIR).map_err(|e| e.to_string())?;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(key_path(crm)).map_err(|e| e.to_string())?;
+    file.write_all(hex::encode(signing_key.to_bytes()).as_bytes()).map_err(|e| e.to_string())?;
+
+    conn.execute("INSERT INTO doctor_keys (crm, public_key, created_at) VALUES (?1, ?2, ?3)",
+                 [crm, &public_hex, &chrono::Utc::now().to_rfc3339()]).map_err(|e| e.to_string())?;
+    Ok(public_hex)
+}
+
+pub fn public_key(conn: &Connection, crm: &str) -> Option<VerifyingKey> {
+    let hex_key: String = conn.query_row("SELECT public_key FROM doctor_keys WHERE crm = ?1", [crm], |row| row.get(0))
+        .optional().ok()??;
+    let bytes: [u8; 32] = hex::decode(hex_key).ok()?.try_into().ok()?;
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
+/// Renames private keys saved under a CRM in another form, such as
+/// `keys/10057/SP.key`, to `keys/10057-SP.key`; returns how many were moved.
+/// A key already present under the normalized name is left alone.
+pub fn normalize_key_files() -> usize {
+    let mut moved = 0;
+    let found: Vec<PathBuf> = walkdir::WalkDir::new(KEYS_DIR).min_depth(1).into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "key"))
+        .collect();
+    for path in found {
+        let Some(raw) = path.strip_prefix(KEYS_DIR).ok().and_then(|p| p.with_extension("").to_str().map(String::from)) else { continue };
+        let target = key_path(&Crm::normalize(&raw));
+        if target != path && !target.exists() && std::fs::rename(&path, &target).is_ok() {
+            moved += 1;
+            if let Some(parent) = path.parent().filter(|p| *p != Path::new(KEYS_DIR)) {
+                let _ = std::fs::remove_dir(parent);
+            }
+        }
+    }
+    moved
+}
+
+/// Whether the doctor's private key is available on this machine.
+pub fn has_signing_key(crm: &str) -> bool {
+    key_path(crm).exists()
+}
+
+fn signing_key(crm: &str) -> Result<SigningKey, String> {
+    let hex_key = std::fs::read_to_string(key_path(crm))
+        .map_err(|_| format!("Chave privada do CRM {} não encontrada em {}/.", crm, KEYS_DIR))?;
+    let bytes: [u8; 32] = hex::decode(hex_key.trim()).ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| format!("Chave privada do CRM {} corrompida.", crm))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// Signs the canonical content hash with the key of the doctor named in the
+/// [DOCTOR] block and returns the content with `Hash:`/`Signature:` filled.
+pub fn sign_content(content: &str) -> Result<String, String> {
+    let cons = medfile::parse_med_file(content).ok_or("Falha ao parsear arquivo .med")?;
+    let key = signing_key(&cons.doctor.crm)?;
+    let hash = content_hash(content);
+    let signature = key.sign(hash.as_bytes());
+
+    let content = medfile::set_transaction_field(content, "Hash", &format!("sha256:{}", hash));
+    Ok(medfile::set_transaction_field(&content, "Signature", &format!("ed25519:{}", BASE64.encode(signature.to_bytes()))))
+}
+
+pub fn verify_content(conn: &Connection, content: &str) -> SignatureStatus {
+    let Some(signature) = medfile::transaction_field(content, "Signature")
+        .and_then(|s| s.strip_prefix("ed25519:").map(str::to_string)) else {
+        return SignatureStatus::Unsigned;
+    };
+    let hash = content_hash(content);
+    if medfile::transaction_field(content, "Hash").as_deref() != Some(format!("sha256:{}", hash).as_str()) {
+        return SignatureStatus::HashMismatch;
+    }
+    let Some(crm) = medfile::parse_med_file(content).map(|c| c.doctor.crm) else {
+        return SignatureStatus::Invalid;
+    };
+    let Some(key) = public_key(conn, &crm) else {
+        return SignatureStatus::UnknownKey;
+    };
+    let signature = BASE64.decode(signature).ok()
+        .and_then(|b| Signature::from_slice(&b).ok());
+    match signature {
+        Some(sig) if key.verify(hash.as_bytes(), &sig).is_ok() => SignatureStatus::Valid,
+        _ => SignatureStatus::Invalid,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIGNED: &str = "[PATIENT]\nCPF: 529.982.247-25\n\n[TRANSACTION]\nID: tx001\nHash: sha256:abc\nSignature: ed25519:xyz\nPrevious: 00\n\n[CONTENT]\nHash: fica\n";
+
+    #[test]
+    fn drops_hash_and_signature_of_the_transaction_only() {
+        assert_eq!(canonical_content(SIGNED),
+                   "[PATIENT]\nCPF: 529.982.247-25\n\n[TRANSACTION]\nID: tx001\nPrevious: 00\n\n[CONTENT]\nHash: fica");
+    }
+
+    #[test]
+    fn same_text_before_and_after_signing() {
+        let unsigned = SIGNED.replace("Hash: sha256:abc\nSignature: ed25519:xyz\n", "");
+        assert_eq!(canonical_content(&unsigned), canonical_content(SIGNED));
+        assert_eq!(content_hash(&unsigned), content_hash(SIGNED));
+    }
+
+    #[test]
+    fn ignores_line_endings_and_trailing_whitespace() {
+        let windows = SIGNED.replace('\n', "  \r\n") + "\r\n\r\n";
+        assert_eq!(canonical_content(&windows), canonical_content(SIGNED));
+    }
+
+    #[test]
+    fn any_other_change_alters_the_hash() {
+        assert_ne!(content_hash(SIGNED), content_hash(&SIGNED.replace("tx001", "tx002")));
+        assert_ne!(content_hash(SIGNED), content_hash(&SIGNED.replace("Previous: 00", "Previous: 01")));
+        assert_ne!(content_hash(SIGNED), content_hash(&SIGNED.replace("\n\n[CONTENT]", "\n[CONTENT]")));
+    }
+}
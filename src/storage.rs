@@ -0,0 +1,222 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use rusqlite::{Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::db;
+
+/// Prefix of encrypted .med files; plain files are read as they are.
+const FILE_MAGIC: &[u8] = b"MYMEDENC1\n";
+/// Prefix of encrypted database values.
+const FIELD_PREFIX: &str = "enc:";
+const NONCE_LEN: usize = 12;
+
+pub fn setting(conn: &Connection, key: &str) -> Option<String> {
+    conn.query_row("SELECT value FROM settings WHERE key = ?1", [key], |row| row.get(0)).optional().ok()?
+}
+
+pub fn set_setting(conn: &Connection, key: &str, value: &str) {
+    conn.execute("INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)", [key, value]).unwrap();
+}
+
+/// Whether new files and database values are written encrypted.
+pub fn enabled() -> bool {
+    setting(&db::open(), "encryption").as_deref() == Some("on")
+}
+
+/// The passphrase from `MYMED_PASSPHRASE`, or the contents of `MYMED_KEYFILE`.
+fn passphrase() -> Option<Vec<u8>> {
+    if let Ok(p) = std::env::var("MYMED_PASSPHRASE") {
+        return Some(p.into_bytes());
+    }
+    std::env::var("MYMED_KEYFILE").ok().and_then(|path| std::fs::read(path).ok())
+}
+
+/// Derives the storage key with argon2 from the passphrase and the salt kept
+/// in `settings`, creating the salt on first use.
+fn key() -> Result<&'static Key, String> {
+    static KEY: OnceLock<Key> = OnceLock::new();
+    if let Some(key) = KEY.get() {
+        return Ok(key);
+    }
+    let passphrase = passphrase().ok_or("Defina MYMED_PASSPHRASE ou MYMED_KEYFILE para acessar os dados criptografados.")?;
+    let conn = db::open();
+    let salt = match setting(&conn, "encryption_salt") {
+        Some(salt) => hex::decode(salt).map_err(|e| e.to_string())?,
+        None => {
+            let mut salt = vec![0u8; 16];
+            OsRng.fill_bytes(&mut salt);
+            set_setting(&conn, "encryption_salt", &hex::encode(&salt));
+            salt
+        }
+    };
+    let mut key = [0u8; 32];
+    argon2::Argon2::default().hash_password_into(&passphrase, &salt, &mut key).map_err(|e| e.to_string())?;
+    Ok(KEY.get_or_init(|| Key::from(key)))
+}
+
+/// A value encrypted under the current key: a .med file or a `patients` row.
+fn encrypted_sample(conn: &Connection) -> Option<Result<(), String>> {
+    let row: Option<String> = conn.query_row("SELECT nome FROM patients WHERE nome LIKE 'enc:%' LIMIT 1", [], |row| row.get(0))
+        .optional().unwrap();
+    if let Some(row) = row {
+        return Some(open_field(&row).map(|_| ()));
+    }
+    walkdir::WalkDir::new(crate::medfile::medfiles_dir()).into_iter().filter_map(|e| e.ok())
+        .filter_map(|e| std::fs::read(e.path()).ok())
+        .find_map(|bytes| bytes.strip_prefix(FILE_MAGIC).map(|data| decrypt(data).map(|_| ())))
+}
+
+/// Fails early when no passphrase is configured, it cannot derive the key,
+/// or the key is not the one the data was encrypted with. The first unlock
+/// stores a sealed `encryption_check` in `settings` to compare against.
+pub fn unlock() -> Result<(), String> {
+    key()?;
+    let conn = db::open();
+    let wrong = |_| "A senha de MYMED_PASSPHRASE/MYMED_KEYFILE não abre os dados criptografados.".to_string();
+    match setting(&conn, "encryption_check") {
+        Some(check) => open_field(&check).map(|_| ()).map_err(wrong),
+        None => {
+            encrypted_sample(&conn).unwrap_or(Ok(())).map_err(wrong)?;
+            set_setting(&conn, "encryption_check", &seal(b"mymed")?);
+            Ok(())
+        }
+    }
+}
+
+/// With the encrypted mode on, checks the key once at startup so a missing
+/// or wrong passphrase is reported before any data is read.
+pub fn check_key(conn: &Connection) -> Result<(), String> {
+    if setting(conn, "encryption").as_deref() == Some("on") {
+        unlock()?;
+    }
+    Ok(())
+}
+
+fn encrypt(plain: &[u8]) -> Result<Vec<u8>, String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let sealed = ChaCha20Poly1305::new(key()?).encrypt(Nonce::from_slice(&nonce), plain).map_err(|e| e.to_string())?;
+    Ok([nonce.as_slice(), &sealed].concat())
+}
+
+fn decrypt(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < NONCE_LEN {
+        return Err("Dado criptografado truncado.".to_string());
+    }
+    let (nonce, sealed) = data.split_at(NONCE_LEN);
+    ChaCha20Poly1305::new(key()?).decrypt(Nonce::from_slice(nonce), sealed)
+        .map_err(|_| "Falha ao descriptografar: senha incorreta ou dado corrompido.".to_string())
+}
+
+pub fn is_encrypted_file(bytes: &[u8]) -> bool {
+    bytes.starts_with(FILE_MAGIC)
+}
+
+/// Reads a .med file, decrypting it when stored encrypted.
+pub fn read_med(path: &Path) -> std::io::Result<String> {
+    let bytes = std::fs::read(path)?;
+    let plain = match bytes.strip_prefix(FILE_MAGIC) {
+        Some(data) => decrypt(data).map_err(std::io::Error::other)?,
+        None => bytes,
+    };
+    String::from_utf8(plain).map_err(std::io::Error::other)
+}
+
+/// Writes a .med file, encrypted when the encrypted storage mode is on.
+pub fn write_med(path: &Path, content: &str) -> std::io::Result<()> {
+    if enabled() {
+        let data = encrypt(content.as_bytes()).map_err(std::io::Error::other)?;
+        std::fs::write(path, [FILE_MAGIC, &data].concat())
+    } else {
+        std::fs::write(path, content)
+    }
+}
+
+/// Rewrites a .med file in the requested form; returns whether it changed.
+pub fn convert_med(path: &Path, encrypted: bool) -> Result<bool, String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    if is_encrypted_file(&bytes) == encrypted {
+        return Ok(false);
+    }
+    let content = read_med(path).map_err(|e| e.to_string())?;
+    let out = if encrypted {
+        [FILE_MAGIC, &encrypt(content.as_bytes())?].concat()
+    } else {
+        content.into_bytes()
+    };
+    std::fs::write(path, out).map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+fn seal(plain: &[u8]) -> Result<String, String> {
+    encrypt(plain).map(|data| format!("{}{}", FIELD_PREFIX, BASE64.encode(data)))
+}
+
+/// Value to store in a sensitive column.
+pub fn seal_field(value: &str) -> Result<String, String> {
+    if !enabled() {
+        return Ok(value.to_string());
+    }
+    seal(value.as_bytes())
+}
+
+/// Value read from a sensitive column, decrypted when needed.
+pub fn open_field(value: &str) -> Result<String, String> {
+    let Some(encoded) = value.strip_prefix(FIELD_PREFIX) else {
+        return Ok(value.to_string());
+    };
+    let data = BASE64.decode(encoded).map_err(|e| e.to_string())?;
+    decrypt(&data).map(|plain| String::from_utf8_lossy(&plain).into_owned())
+}
+
+/// Encrypts (or decrypts) the sensitive columns of `patients`; returns the
+/// number of rows rewritten.
+pub fn convert_patients(conn: &Connection, encrypted: bool) -> Result<usize, String> {
+    let rows: Vec<(String, String, String)> = conn.prepare("SELECT cpf, nome, birth_date FROM patients").unwrap()
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))).unwrap()
+        .filter_map(|r| r.ok())
+        .filter(|(_, nome, _)| nome.starts_with(FIELD_PREFIX) != encrypted)
+        .collect();
+    for (cpf, nome, birth_date) in &rows {
+        let (nome, birth_date) = (open_field(nome)?, open_field(birth_date)?);
+        let (nome, birth_date) = if encrypted {
+            (seal_field(&nome)?, seal_field(&birth_date)?)
+        } else {
+            (nome, birth_date)
+        };
+        conn.execute("UPDATE patients SET nome = ?2, birth_date = ?3 WHERE cpf = ?1", [cpf, &nome, &birth_date]).unwrap();
+    }
+    Ok(rows.len())
+}
+
+/// Encrypts (or decrypts) `patient_medications`: drug, dosage and start date.
+/// Encrypted rows drop the `med_id` link and carry the drug name sealed in
+/// `drug`; decrypted rows link to `medications` again. Returns the number of
+/// rows rewritten.
+pub fn convert_medications(conn: &Connection, encrypted: bool) -> Result<usize, String> {
+    let rows: Vec<(i64, String, String, String)> = conn.prepare(
+        "SELECT pm.rowid, COALESCE(pm.drug, m.name), pm.dosage, pm.start_date FROM patient_medications pm
+         LEFT JOIN medications m ON pm.med_id = m.id").unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get::<_, String>(1)?, row.get(2)?, row.get(3)?))).unwrap()
+        .filter_map(|r| r.ok())
+        .filter(|(_, drug, _, _)| drug.starts_with(FIELD_PREFIX) != encrypted)
+        .collect();
+    for (rowid, drug, dosage, start_date) in &rows {
+        let (drug, dosage, start_date) = (open_field(drug)?, open_field(dosage)?, open_field(start_date)?);
+        if encrypted {
+            conn.execute("UPDATE patient_medications SET med_id = NULL, drug = ?2, dosage = ?3, start_date = ?4 WHERE rowid = ?1",
+                         (rowid, seal(drug.as_bytes())?, seal(dosage.as_bytes())?, seal(start_date.as_bytes())?)).unwrap();
+        } else {
+            conn.execute("INSERT OR IGNORE INTO medications (name) VALUES (?1)", [&drug]).unwrap();
+            conn.execute("UPDATE patient_medications SET med_id = (SELECT id FROM medications WHERE name = ?2), drug = NULL,
+                          dosage = ?3, start_date = ?4 WHERE rowid = ?1", (rowid, &drug, &dosage, &start_date)).unwrap();
+        }
+    }
+    Ok(rows.len())
+}
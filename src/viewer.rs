@@ -1,13 +1,11 @@
 use clap::{Args, Parser, Subcommand};
+use crossterm::style::{Color, Stylize};
+use mymed::cpf::Cpf;
+use mymed::diagnostics::{self, DirectiveKind};
+use mymed::medfile::{self, Consultation};
+use mymed::{audit, consult, db, ledger, medications, signature, storage};
+use regex::Regex;
 use rusqlite::Connection;
-use chrono;
-
-#[derive(Debug)]
-struct Medication {
-    name: String,
-    dosage: String,
-    start_date: String,
-}
 
 #[derive(Parser)]
 #[command(name = "mymed_viewer")]
@@ -20,68 +18,296 @@ struct Cli {
 enum Commands {
     Patient(PatientArgs),
     File(FileArgs),
+    Verify(FileArgs),
 }
 
 #[derive(Args)]
 struct PatientArgs {
     #[arg(long)]
-    cpf: String,
+    cpf: Cpf,
+    /// Só consultas a partir desta data (AAAA-MM-DD ou DD/MM/AAAA)
+    #[arg(long)]
+    since: Option<String>,
+    /// Especialidade, sem diferenciar maiúsculas e acentos
+    #[arg(long)]
+    specialty: Option<String>,
+    /// CRM ou parte do nome do médico
+    #[arg(long)]
+    doctor: Option<String>,
+    #[arg(long, default_value_t = 1)]
+    page: usize,
+    #[arg(long, default_value_t = 10)]
+    per_page: usize,
+    #[arg(long)]
+    json: bool,
 }
 
 #[derive(Args)]
 struct FileArgs {
     #[arg(long)]
     path: String,
+    #[arg(long)]
+    json: bool,
 }
 
-fn calculate_age(birth_date: &str) -> String {
-    let birth = chrono::NaiveDate::parse_from_str(birth_date, "%Y-%m-%d").unwrap_or(chrono::Utc::now().date_naive());
-    let today = chrono::Utc::now().date_naive();
-    let age = (today - birth).num_days() / 365;
-    age.to_string()
+/// Colors `text` when writing to a terminal, unless `NO_COLOR` is set.
+fn paint(text: &str, color: Color) -> String {
+    if atty::is(atty::Stream::Stdout) && std::env::var_os("NO_COLOR").is_none() {
+        text.with(color).to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+fn directive_color(name: &str) -> Color {
+    match diagnostics::directive_kind(name) {
+        Some(DirectiveKind::Medication) => Color::Green,
+        Some(DirectiveKind::Exam) => Color::Cyan,
+        Some(DirectiveKind::History) => Color::Magenta,
+        Some(DirectiveKind::Note) => Color::Yellow,
+        None => Color::Red,
+    }
+}
+
+/// Where the file stands in the patient's ledger: `sealed`, `altered` (the
+/// ledger holds another hash) or `unsealed`.
+fn ledger_status(conn: &Connection, cons: &Consultation, content: &str) -> (&'static str, String) {
+    match ledger::entries(conn, &cons.patient.cpf).into_iter().find(|e| e.tx_id == cons.tx_id) {
+        Some(entry) if entry.hash == signature::content_hash(content) => ("sealed", format!("registrado no ledger (#{})", entry.seq)),
+        Some(entry) => ("altered", format!("difere do registrado no ledger (#{})", entry.seq)),
+        None => ("unsealed", "não registrado no ledger".to_string()),
+    }
+}
+
+/// Timeline of the patient's consultations, oldest first, each with the
+/// medication changes of its [CONDUTA].
+fn view_patient(args: &PatientArgs) {
+    let since = match args.since.as_deref().map(|s| {
+        chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").or_else(|_| chrono::NaiveDate::parse_from_str(s, "%d/%m/%Y"))
+    }) {
+        Some(Ok(date)) => Some(date.format("%Y-%m-%d").to_string()),
+        Some(Err(_)) => {
+            println!("Data inválida em --since. Use AAAA-MM-DD ou DD/MM/AAAA.");
+            std::process::exit(2);
+        }
+        None => None,
+    };
+    if args.page == 0 || args.per_page == 0 {
+        println!("--page e --per-page começam em 1.");
+        std::process::exit(2);
+    }
+    db::setup_db();
+    let conn = db::open();
+    let Some(mut chart) = consult::chart(&conn, &args.cpf) else {
+        println!("Paciente não encontrado.");
+        std::process::exit(1);
+    };
+    audit::record(&conn, &audit::cli_user(), "read", Some(&chart.patient.cpf), "mymed_viewer patient", None);
+    medfile::link_amendments(&mut chart.history);
+
+    // Changes are replayed over the whole history so filtered visits still
+    // show what they changed.
+    let mut meds = medications::MedicationList::new();
+    let visits: Vec<(&Consultation, Vec<medications::MedicationChange>)> = chart.history.iter()
+        .map(|c| (c, medications::apply_conduta(&mut meds, &c.conduta)))
+        .collect();
+    let specialty = args.specialty.as_deref().map(medfile::fold);
+    let doctor = args.doctor.as_deref().map(medfile::fold);
+    let selected: Vec<(usize, &(&Consultation, Vec<medications::MedicationChange>))> = visits.iter().enumerate()
+        .filter(|(_, (c, _))| since.as_ref().is_none_or(|s| c.timestamp.as_str() >= s.as_str()))
+        .filter(|(_, (c, _))| specialty.as_ref().is_none_or(|s| medfile::fold(&c.doctor.especialidade).contains(s.as_str())))
+        .filter(|(_, (c, _))| doctor.as_ref().is_none_or(|d| medfile::fold(&c.doctor.crm).starts_with(d.as_str()) || medfile::fold(&c.doctor.nome).contains(d.as_str())))
+        .collect();
+    let pages = selected.len().div_ceil(args.per_page).max(1);
+    let page: Vec<_> = selected.iter().skip((args.page - 1) * args.per_page).take(args.per_page).collect();
+
+    if args.json {
+        let consultations: Vec<_> = page.iter().map(|(n, (c, changes))| serde_json::json!({
+            "number": n + 1,
+            "consultation": c,
+            "medication_changes": changes,
+        })).collect();
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+            "patient": chart.patient,
+            "age": medfile::calculate_age(&chart.patient.birth_date),
+            "current_medications": chart.medications,
+            "total": selected.len(),
+            "page": args.page,
+            "pages": pages,
+            "consultations": consultations,
+        })).unwrap());
+        return;
+    }
+
+    println!("Paciente: {} - CPF: {}", chart.patient.nome, chart.patient.cpf);
+    println!("Idade: {}", medfile::calculate_age(&chart.patient.birth_date));
+    println!("Medicações Atuais:");
+    for (name, dosage) in &chart.medications {
+        println!("- {} {}", name, dosage);
+    }
+    println!("\nConsultas:");
+    if selected.is_empty() {
+        println!("(nenhuma consulta encontrada)");
+        return;
+    }
+    for (n, (c, changes)) in &page {
+        println!("\n{} {} - {} ({}) - CRM {}", paint(&format!("#{}", n + 1), Color::Blue), c.timestamp.get(..10).unwrap_or(&c.timestamp),
+                 c.doctor.nome, c.doctor.especialidade, c.doctor.crm);
+        println!("  {} - {}", c.tx_id, c.filename);
+        if let Some(amends) = &c.amends {
+            println!("  Adendo de {}", amends);
+        }
+        if !c.amended_by.is_empty() {
+            println!("  Corrigida por {}", c.amended_by.join(", "));
+        }
+        for (title, text) in [("Hipótese", &c.hipotese_diagnostica), ("Conduta", &c.conduta)] {
+            if !text.is_empty() {
+                println!("  {}:", title);
+                for line in text.lines() {
+                    println!("    {}", line.trim());
+                }
+            }
+        }
+        if !changes.is_empty() {
+            println!("  Medicações:");
+            for change in changes {
+                println!("    {}", change.describe());
+            }
+        }
+    }
+    println!("\nPágina {} de {} ({} consultas).", args.page, pages, selected.len());
+    if args.page < pages {
+        println!("Use --page {} para continuar.", args.page + 1);
+    }
+}
+
+fn view_file(args: &FileArgs) {
+    let content = match storage::read_med(std::path::Path::new(&args.path)) {
+        Ok(c) => c,
+        Err(_) => {
+            println!("Arquivo não encontrado.");
+            std::process::exit(1);
+        }
+    };
+    let found = diagnostics::check(&content);
+    let Some(cons) = medfile::parse_med_file(&content) else {
+        for d in &found {
+            println!("{}", d.describe());
+        }
+        println!("Falha ao parsear arquivo .med");
+        std::process::exit(1);
+    };
+    db::setup_db();
+    let conn = db::open();
+    audit::record(&conn, &audit::cli_user(), "read", Some(&cons.patient.cpf), "mymed_viewer file", None);
+
+    // Medications in use right before this consultation.
+    let earlier: Vec<Consultation> = medfile::load_consultations().into_iter()
+        .filter(|c| c.patient.cpf == cons.patient.cpf && c.tx_id != cons.tx_id && c.timestamp < cons.timestamp)
+        .collect();
+    let mut meds = medications::current(&earlier);
+    let changes = medications::apply_conduta(&mut meds, &cons.conduta);
+    let exams = medfile::parse_exams(&content);
+    let sections = medfile::content_sections(&content);
+    let status = signature::verify_content(&conn, &content);
+    let (ledger_state, ledger_label) = ledger_status(&conn, &cons, &content);
+
+    if args.json {
+        let sections: Vec<_> = sections.iter().map(|(name, text)| serde_json::json!({ "name": name, "text": text })).collect();
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+            "file": args.path,
+            "consultation": cons,
+            "sections": sections,
+            "medication_changes": changes,
+            "exams": exams,
+            "diagnostics": found,
+            "integrity": {
+                "hash": signature::content_hash(&content),
+                "signature": status,
+                "signature_label": status.label(),
+                "ledger": ledger_state,
+            },
+        })).unwrap());
+        return;
+    }
+
+    println!("{} {} - CPF {} - {} anos", paint("Paciente:", Color::Blue), cons.patient.nome, cons.patient.cpf,
+             medfile::calculate_age(&cons.patient.birth_date));
+    println!("{} {} - CRM {} - {}", paint("Médico:", Color::Blue), cons.doctor.nome, cons.doctor.crm, cons.doctor.especialidade);
+    println!("{} {} - {}", paint("Transação:", Color::Blue), cons.tx_id, cons.timestamp);
+    if let Some(amends) = &cons.amends {
+        println!("{} {}", paint("Adendo de:", Color::Blue), amends);
+    }
+
+    let directive_re = Regex::new(r"!([A-Za-z]\w*)").unwrap();
+    for (name, text) in &sections {
+        println!("\n{}", paint(&format!("[{}]", name), Color::Blue));
+        for line in text.lines() {
+            let colored = directive_re.replace_all(line, |cap: &regex::Captures| paint(&cap[0], directive_color(&cap[1])));
+            println!("  {}", colored);
+        }
+    }
+
+    if !changes.is_empty() {
+        println!("\n{}", paint("Medicações", Color::Blue));
+        for change in &changes {
+            println!("  {}", change.describe());
+        }
+    }
+
+    if !exams.is_empty() {
+        let code_width = exams.iter().map(|e| e.code.chars().count()).max().unwrap_or(0).max("Exame".len());
+        let date_width = exams.iter().map(|e| e.date.chars().count()).max().unwrap_or(0).max("Data".len());
+        println!("\n{}", paint("Exames", Color::Blue));
+        println!("  {:<code_width$}  {:<date_width$}  Resultado", "Exame", "Data");
+        for exam in &exams {
+            println!("  {:<code_width$}  {:<date_width$}  {}", exam.code, exam.date, exam.result);
+        }
+    }
+
+    println!("\n{}", paint("Integridade", Color::Blue));
+    println!("  Hash: sha256:{}", signature::content_hash(&content));
+    let status_color = if status == signature::SignatureStatus::Valid { Color::Green } else { Color::Yellow };
+    println!("  Assinatura: {}", paint(status.label(), status_color));
+    println!("  Ledger: {}", paint(&ledger_label, if ledger_state == "altered" { Color::Red } else { Color::Reset }));
+    for d in &found {
+        let color = if d.severity == diagnostics::Severity::Error { Color::Red } else { Color::Yellow };
+        println!("  {}", paint(&d.describe(), color));
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
     match cli.command {
-        Commands::Patient(args) => {
-            let conn = Connection::open("medications.db").unwrap();
-            // get patient
-            let mut stmt = conn.prepare("SELECT nome, birth_date FROM patients WHERE cpf = ?1").unwrap();
-            let patient = stmt.query_row([&args.cpf], |row| {
-                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-            });
-            match patient {
-                Ok((nome, birth_date)) => {
-                    let age = calculate_age(&birth_date);
-                    println!("Paciente: {} - CPF: {}", nome, args.cpf);
-                    println!("Idade: {}", age);
-                    // get medications
-                    let mut med_stmt = conn.prepare("SELECT m.name, pm.dosage, pm.start_date FROM patient_medications pm JOIN medications m ON pm.med_id = m.id WHERE pm.cpf = ?1 AND pm.status = 'active'").unwrap();
-                    let meds = med_stmt.query_map([&args.cpf], |row| {
-                        Ok(Medication {
-                            name: row.get(0)?,
-                            dosage: row.get(1)?,
-                            start_date: row.get(2)?,
-                        })
-                    }).unwrap();
-                    println!("Medicações Atuais:");
-                    for med in meds {
-                        if let Ok(m) = med {
-                            println!("- {} {} (desde {})", m.name, m.dosage, m.start_date);
-                        }
-                    }
-                    // TODO: add consultations from files
-                    println!("Consultas: (implementar parsing de .med)");
-                }
+        Commands::Patient(args) => view_patient(&args),
+        Commands::File(args) => view_file(&args),
+        Commands::Verify(args) => {
+            let content = match storage::read_med(std::path::Path::new(&args.path)) {
+                Ok(c) => c,
                 Err(_) => {
-                    println!("Paciente não encontrado.");
+                    println!("Arquivo não encontrado.");
+                    return;
                 }
+            };
+            db::setup_db();
+            let conn = db::open();
+            let status = signature::verify_content(&conn, &content);
+            if let Some(cons) = medfile::parse_med_file(&content) {
+                audit::record(&conn, &audit::cli_user(), "verify", Some(&cons.patient.cpf), "mymed_viewer verify", None);
+            }
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+                    "hash": signature::content_hash(&content),
+                    "status": status,
+                    "label": status.label(),
+                })).unwrap());
+            } else {
+                println!("Hash: sha256:{}", signature::content_hash(&content));
+                println!("Assinatura: {}", status.label());
+            }
+            if status != signature::SignatureStatus::Valid {
+                std::process::exit(1);
             }
-        }
-        Commands::File(args) => {
-            // TODO: parse and display file
-            println!("View file {}", args.path);
         }
     }
 }
\ No newline at end of file